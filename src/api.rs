@@ -0,0 +1,290 @@
+use crate::auth::{self, AuthenticatedClient, Authenticator};
+use crate::jobs::{Job, JobFilter, JobId, JobInput, JobManagerActor, JobSpec, JobStats};
+use crate::ratelimit::{self, RateLimitUsage, RateLimiter};
+use crate::request::{Image, ImageRequest, ImageRequestBody};
+use crate::source::SourceRegistry;
+use act_zero::{call, send, Addr};
+use axum::extract::{DefaultBodyLimit, Multipart, Path, Query, State};
+use axum::http::StatusCode;
+use axum::middleware;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub struct AppState {
+    pub sources: Arc<SourceRegistry>,
+    pub jobs: Addr<JobManagerActor>,
+    /// Names of the configured targets.
+    pub targets: Vec<String>,
+    pub limiter: Arc<RateLimiter>,
+}
+
+pub fn router(state: AppState, auth: Authenticator, max_upload_size: usize) -> Router {
+    let ingestion = Router::new()
+        .route("/post", post(pixiv_handler))
+        .route("/v1/requests", post(create_request))
+        .route(
+            "/v1/upload",
+            post(upload).layer(DefaultBodyLimit::max(max_upload_size)),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.limiter.clone(),
+            ratelimit::limit,
+        ));
+
+    Router::new()
+        .merge(ingestion)
+        .route("/v1/jobs", get(list_jobs))
+        .route("/v1/jobs/:id", get(get_job))
+        .route("/v1/status", get(status))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::new(auth),
+            auth::authenticate,
+        ))
+        .with_state(Arc::new(state))
+}
+
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = Json(serde_json::json!({ "error": self.message }));
+        (self.status, body).into_response()
+    }
+}
+
+async fn pixiv_handler(
+    State(app_state): State<Arc<AppState>>,
+    body: String,
+) -> &'static str {
+    for link in app_state.sources.find_links(&body) {
+        send!(app_state.jobs.submit(JobSpec::new(JobInput::Link(link))));
+    }
+
+    "Ok"
+}
+
+#[derive(Deserialize)]
+struct CreateRequest {
+    url: String,
+    targets: Option<Vec<String>>,
+    caption: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    priority: i32,
+}
+
+#[derive(Serialize)]
+struct CreatedJob {
+    job_id: JobId,
+    source: String,
+    url: String,
+}
+
+async fn create_request(
+    State(app_state): State<Arc<AppState>>,
+    client: Option<Extension<AuthenticatedClient>>,
+    Json(request): Json<CreateRequest>,
+) -> Result<(StatusCode, Json<CreatedJob>), ApiError> {
+    let client = client.map_or_else(|| "anonymous".to_owned(), |Extension(client)| client.0);
+    log::info!("Request for {} from {}", request.url, client);
+
+    let link = app_state
+        .sources
+        .find_links(&request.url)
+        .into_iter()
+        .next()
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Unsupported url: {}", request.url),
+            )
+        })?;
+
+    check_targets(&app_state, &request.targets)?;
+
+    submit(
+        &app_state,
+        JobSpec {
+            input: JobInput::Link(link),
+            targets: request.targets,
+            caption: request.caption,
+            tags: request.tags,
+            priority: request.priority,
+        },
+    )
+    .await
+}
+
+/// Accepts `file` fields with the images to send, and optional `caption`, `source`,
+/// comma separated `tags` and `targets`, and `priority` fields.
+async fn upload(
+    State(app_state): State<Arc<AppState>>,
+    client: Option<Extension<AuthenticatedClient>>,
+    mut multipart: Multipart,
+) -> Result<(StatusCode, Json<CreatedJob>), ApiError> {
+    let mut images = Vec::new();
+    let mut source = String::new();
+    let mut caption = None;
+    let mut tags = Vec::new();
+    let mut targets = None;
+    let mut priority = 0;
+
+    let bad_request = |e: axum::extract::multipart::MultipartError| {
+        ApiError::new(StatusCode::BAD_REQUEST, e.to_string())
+    };
+
+    while let Some(field) = multipart.next_field().await.map_err(bad_request)? {
+        match field.name().unwrap_or_default() {
+            "file" => {
+                let filename = field
+                    .file_name()
+                    .map(str::to_owned)
+                    .unwrap_or_else(|| format!("upload_{}", images.len() + 1));
+                let data = field.bytes().await.map_err(bad_request)?;
+
+                // The senders re-encode every upload, so it has to decode rather than look like an image
+                if image::load_from_memory(&data).is_err() {
+                    return Err(ApiError::new(
+                        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                        format!("{} is not an image", filename),
+                    ));
+                }
+
+                images.push(Image::new(filename, data.to_vec().into()));
+            }
+            "caption" => caption = Some(field.text().await.map_err(bad_request)?),
+            "source" => source = field.text().await.map_err(bad_request)?,
+            "tags" => tags.extend(split_list(&field.text().await.map_err(bad_request)?)),
+            "targets" => {
+                let list = split_list(&field.text().await.map_err(bad_request)?);
+                targets.get_or_insert_with(Vec::new).extend(list);
+            }
+            "priority" => {
+                priority = field
+                    .text()
+                    .await
+                    .map_err(bad_request)?
+                    .trim()
+                    .parse()
+                    .map_err(|_| ApiError::new(StatusCode::BAD_REQUEST, "Invalid priority"))?;
+            }
+            _ => {}
+        }
+    }
+
+    let client = client.map_or_else(|| "anonymous".to_owned(), |Extension(client)| client.0);
+    log::info!("Upload of {} images from {}", images.len(), client);
+
+    check_targets(&app_state, &targets)?;
+    let body = ImageRequestBody::from_images(images)
+        .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "No file uploaded"))?;
+
+    let mut request = ImageRequest::new(source, body);
+    request.caption = caption;
+
+    submit(
+        &app_state,
+        JobSpec {
+            input: JobInput::Upload(request),
+            targets,
+            caption: None,
+            tags,
+            priority,
+        },
+    )
+    .await
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+fn check_targets(app_state: &AppState, targets: &Option<Vec<String>>) -> Result<(), ApiError> {
+    match targets
+        .iter()
+        .flatten()
+        .find(|target| !app_state.targets.contains(target))
+    {
+        Some(unknown) => Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Unknown target: {}", unknown),
+        )),
+        None => Ok(()),
+    }
+}
+
+async fn submit(
+    app_state: &AppState,
+    spec: JobSpec,
+) -> Result<(StatusCode, Json<CreatedJob>), ApiError> {
+    let source = spec.input.source().to_owned();
+    let url = spec.input.url().to_owned();
+    let job_id = call!(app_state.jobs.submit(spec))
+        .await
+        .map_err(|_| jobs_unavailable())?;
+
+    Ok((StatusCode::ACCEPTED, Json(CreatedJob { job_id, source, url })))
+}
+
+async fn get_job(
+    State(app_state): State<Arc<AppState>>,
+    Path(id): Path<JobId>,
+) -> Result<Json<Job>, ApiError> {
+    call!(app_state.jobs.get(id))
+        .await
+        .map_err(|_| jobs_unavailable())?
+        .map(Json)
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, format!("Unknown job: {}", id)))
+}
+
+async fn list_jobs(
+    State(app_state): State<Arc<AppState>>,
+    Query(filter): Query<JobFilter>,
+) -> Result<Json<Vec<Job>>, ApiError> {
+    call!(app_state.jobs.list(filter))
+        .await
+        .map(Json)
+        .map_err(|_| jobs_unavailable())
+}
+
+#[derive(Serialize)]
+struct Status {
+    jobs: JobStats,
+    rate_limits: Vec<RateLimitUsage>,
+}
+
+async fn status(State(app_state): State<Arc<AppState>>) -> Result<Json<Status>, ApiError> {
+    let jobs = call!(app_state.jobs.stats())
+        .await
+        .map_err(|_| jobs_unavailable())?;
+
+    Ok(Json(Status {
+        jobs,
+        rate_limits: app_state.limiter.usage(),
+    }))
+}
+
+fn jobs_unavailable() -> ApiError {
+    ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "Job queue is not available")
+}
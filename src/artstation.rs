@@ -0,0 +1,174 @@
+use crate::config::Config;
+use crate::request::{Image, ImageRequest, ImageRequestBody};
+use crate::source::{FetchResult, LinkPattern, Source};
+use crate::utils::ResultExtension;
+use act_zero::{Actor, ActorResult, Produces};
+use futures::future::join_all;
+use serde::Deserialize;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+struct Project {
+    #[serde(default)]
+    title: String,
+    user: User,
+    #[serde(default)]
+    adult_content: bool,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    full_name: String,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    /// `image`, `cover`, `video`, `video_clip`, `model3d` and so on.
+    asset_type: String,
+    image_url: Option<String>,
+}
+
+/// Receives artwork projects from ArtStation.
+pub struct ArtStationReceiveActor {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl Actor for ArtStationReceiveActor {}
+
+impl ArtStationReceiveActor {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: config.artstation_base_url.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    /// Downloads the `4k` size of the asset, which only exists for large images,
+    /// and the `large` size otherwise.
+    async fn download(&self, url: &str) -> anyhow::Result<Image> {
+        let mut response = None;
+        for size in ["/4k/", "/large/"].iter() {
+            let sized = url.replacen("/large/", size, 1);
+            let sent = self.client.get(&sized).send().await?;
+            if sent.status().is_success() {
+                response = Some(sent);
+                break;
+            }
+        }
+
+        let data = response
+            .ok_or_else(|| anyhow::anyhow!("No size of {} is available", url))?
+            .bytes()
+            .await
+            .on_error(|_| log::error!("Error on download image"))?;
+
+        let path = url.split('?').next().unwrap();
+        let filename = path.rsplit('/').next().unwrap().to_string();
+        Ok(Image::new(filename, data.as_ref().into()))
+    }
+
+    async fn receive_project(&mut self, hash: &str, url: String) -> FetchResult {
+        log::info!("Start artstation process {}", hash);
+
+        let project: Project = self
+            .client
+            .get(format!("{}/projects/{}.json", self.base_url, hash))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .on_error(|_| log::error!("Error on request project"))?;
+
+        let (assets, skipped): (Vec<_>, Vec<_>) = project
+            .assets
+            .iter()
+            .partition(|asset| asset.asset_type == "image");
+        if !skipped.is_empty() {
+            log::info!("Skip {} video or embedded assets of {}", skipped.len(), hash);
+        }
+
+        let images: Vec<_> = join_all(
+            assets
+                .iter()
+                .filter_map(|asset| asset.image_url.as_deref())
+                .map(|url| self.download(url)),
+        )
+        .await
+        .into_iter()
+        .filter_map(|r| r.on_error(|e| log::error!("{}", e)).ok())
+        .collect();
+
+        log::info!("Downloaded {} images", images.len());
+
+        let body = ImageRequestBody::from_images(images)
+            .ok_or_else(|| anyhow::anyhow!("Project {} has no images", hash))?;
+        let mut request = ImageRequest::new(url, body);
+        request.caption = Some(
+            Some(project.title.trim())
+                .filter(|title| !title.is_empty())
+                .map(|title| format!("{}\n\n{}", title, project.user.full_name))
+                .unwrap_or(project.user.full_name),
+        );
+        request.nsfw = project.adult_content;
+        Ok(request)
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for ArtStationReceiveActor {
+    fn name(&self) -> &str {
+        "artstation"
+    }
+
+    /// Recognizes artwork links and project links on artist subdomains.
+    fn link_patterns(&self) -> Vec<LinkPattern> {
+        vec![
+            LinkPattern::new(
+                r"https?://(?:www\.)?artstation\.com/artwork/(?P<hash>\w+)",
+                "https://www.artstation.com/artwork/$hash",
+            ),
+            LinkPattern::new(
+                r"https?://[\w-]+\.artstation\.com/projects/(?P<hash>\w+)",
+                "https://www.artstation.com/artwork/$hash",
+            ),
+        ]
+    }
+
+    async fn fetch(&mut self, url: String) -> ActorResult<FetchResult> {
+        let hash = url
+            .strip_prefix("https://www.artstation.com/artwork/")
+            .map(str::to_owned);
+
+        Produces::ok(match hash {
+            Some(hash) => self.receive_project(&hash, url).await,
+            None => Err(anyhow::anyhow!("{} is not an artstation artwork", url)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::source::canonical_links;
+
+    #[test]
+    fn recognizes_artworks_and_portfolio_projects() {
+        let source = ArtStationReceiveActor::new(test_config(&[]));
+
+        assert_eq!(
+            canonical_links(
+                &source,
+                "https://www.artstation.com/artwork/AbC123 https://artist.artstation.com/projects/DeF456"
+            ),
+            vec![
+                "https://www.artstation.com/artwork/AbC123",
+                "https://www.artstation.com/artwork/DeF456",
+            ]
+        );
+        assert!(canonical_links(&source, "https://www.artstation.com/artist").is_empty());
+    }
+}
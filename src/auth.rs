@@ -0,0 +1,277 @@
+use crate::api::ApiError;
+use crate::config::ClientConfig;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// How far the `X-Timestamp` of a signed request may be from the server time, in seconds.
+const SIGNATURE_TOLERANCE: i64 = 300;
+
+/// Name of the client a request was authenticated as, added to the request extensions.
+#[derive(Clone, Debug)]
+pub struct AuthenticatedClient(pub String);
+
+/// Checks requests against the configured clients.
+///
+/// A client authenticates either with `Authorization: Bearer <token>` or by signing the
+/// request: `X-Client-Id` names the client, `X-Timestamp` holds the unix time and
+/// `X-Signature` is `sha256=<hex HMAC-SHA256 of "<timestamp>.<body>">`.
+/// A signature is accepted only once.
+pub struct Authenticator {
+    clients: Vec<ClientConfig>,
+    /// Lets every request through, set explicitly by the operator.
+    disabled: bool,
+    /// Largest body read to check a signature.
+    max_body_size: usize,
+    /// Signatures seen within the tolerance window, with their timestamps.
+    seen_signatures: Mutex<HashMap<String, i64>>,
+}
+
+impl Authenticator {
+    pub fn new(clients: Vec<ClientConfig>, max_body_size: usize, disabled: bool) -> Self {
+        if disabled {
+            log::warn!("Api authentication is disabled, ingestion api is open to everyone");
+        } else if clients.is_empty() {
+            log::warn!("No api clients configured, every ingestion api request is rejected");
+        }
+
+        Self {
+            clients,
+            disabled,
+            max_body_size,
+            seen_signatures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn client(&self, name: &str) -> Result<&ClientConfig, String> {
+        let client = self
+            .clients
+            .iter()
+            .find(|client| client.name == name)
+            .ok_or_else(|| format!("unknown client {}", name))?;
+
+        if client.disabled {
+            return Err(format!("client {} is disabled", name));
+        }
+        Ok(client)
+    }
+
+    fn check_token(&self, token: &str) -> Result<String, String> {
+        let client = self
+            .clients
+            .iter()
+            .find(|client| {
+                client
+                    .token
+                    .as_ref()
+                    .is_some_and(|t| constant_time_eq(t.as_bytes(), token.as_bytes()))
+            })
+            .ok_or("invalid token")?;
+
+        self.client(&client.name).map(|client| client.name.clone())
+    }
+
+    fn check_signature(&self, headers: &HeaderMap, body: &[u8]) -> Result<String, String> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .ok_or_else(|| format!("missing {} header", name))
+        };
+
+        let client = self.client(header("x-client-id")?)?;
+        let secret = client
+            .hmac_secret
+            .as_ref()
+            .ok_or_else(|| format!("client {} can not sign requests", client.name))?;
+
+        let timestamp: i64 = header("x-timestamp")?
+            .parse()
+            .map_err(|_| "invalid timestamp")?;
+        let now = chrono::Utc::now().timestamp();
+        if (now - timestamp).abs() > SIGNATURE_TOLERANCE {
+            return Err("timestamp is out of the allowed window".to_owned());
+        }
+
+        let signature = header("x-signature")?;
+        let signature = hex::decode(signature.trim_start_matches("sha256="))
+            .map_err(|_| "invalid signature encoding")?;
+
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key size");
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        mac.verify_slice(&signature)
+            .map_err(|_| "signature mismatch")?;
+
+        let mut seen = self.seen_signatures.lock().unwrap();
+        seen.retain(|_, seen_at| (now - *seen_at).abs() <= SIGNATURE_TOLERANCE);
+        if seen.insert(hex::encode(&signature), timestamp).is_some() {
+            return Err("signature was already used".to_owned());
+        }
+
+        Ok(client.name.clone())
+    }
+}
+
+pub async fn authenticate(
+    State(auth): State<Arc<Authenticator>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if auth.disabled {
+        return next.run(request).await;
+    }
+
+    let bearer = request
+        .headers()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_owned);
+
+    let (parts, body) = request.into_parts();
+    let (client, body) = match bearer {
+        Some(token) => (auth.check_token(&token), body),
+        None => match axum::body::to_bytes(body, auth.max_body_size).await {
+            Ok(bytes) => (
+                auth.check_signature(&parts.headers, &bytes),
+                Body::from(bytes),
+            ),
+            Err(e) => (Err(format!("error on read body: {}", e)), Body::empty()),
+        },
+    };
+
+    match client {
+        Ok(client) => {
+            let mut request = Request::from_parts(parts, body);
+            request.extensions_mut().insert(AuthenticatedClient(client));
+            next.run(request).await
+        }
+        Err(reason) => {
+            let forwarded = parts
+                .headers
+                .get("x-forwarded-for")
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("-");
+            log::warn!(
+                "Rejected {} {} from {}: {}",
+                parts.method,
+                parts.uri,
+                forwarded,
+                reason
+            );
+            ApiError::new(StatusCode::UNAUTHORIZED, "Unauthorized").into_response()
+        }
+    }
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(name: &str) -> ClientConfig {
+        ClientConfig {
+            name: name.to_owned(),
+            token: Some(format!("{}-token", name)),
+            hmac_secret: Some(format!("{}-secret", name)),
+            disabled: false,
+            rate_limit_burst: None,
+            rate_limit_per_minute: None,
+            daily_quota: None,
+        }
+    }
+
+    fn authenticator() -> Authenticator {
+        let disabled = ClientConfig {
+            disabled: true,
+            ..client("disabled")
+        };
+        Authenticator::new(vec![client("ci"), disabled], 1024, false)
+    }
+
+    fn signed(client: &str, secret: &str, timestamp: i64, body: &[u8]) -> HeaderMap {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(format!("{}.", timestamp).as_bytes());
+        mac.update(body);
+        let signature = hex::encode(mac.finalize().into_bytes());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-client-id", client.parse().unwrap());
+        headers.insert("x-timestamp", timestamp.to_string().parse().unwrap());
+        headers.insert("x-signature", format!("sha256={}", signature).parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn accepts_a_signature_once() {
+        let auth = authenticator();
+        let headers = signed("ci", "ci-secret", chrono::Utc::now().timestamp(), b"{}");
+
+        assert_eq!(auth.check_signature(&headers, b"{}"), Ok("ci".to_owned()));
+        assert_eq!(
+            auth.check_signature(&headers, b"{}"),
+            Err("signature was already used".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_signatures() {
+        let auth = authenticator();
+        let now = chrono::Utc::now().timestamp();
+
+        let tampered = signed("ci", "ci-secret", now, b"{}");
+        assert_eq!(
+            auth.check_signature(&tampered, b"{\"url\":\"x\"}"),
+            Err("signature mismatch".to_owned())
+        );
+        let wrong_key = signed("ci", "other-secret", now, b"{}");
+        assert_eq!(
+            auth.check_signature(&wrong_key, b"{}"),
+            Err("signature mismatch".to_owned())
+        );
+        let stale = signed("ci", "ci-secret", now - SIGNATURE_TOLERANCE - 1, b"{}");
+        assert_eq!(
+            auth.check_signature(&stale, b"{}"),
+            Err("timestamp is out of the allowed window".to_owned())
+        );
+        let unknown = signed("nobody", "ci-secret", now, b"{}");
+        assert!(auth.check_signature(&unknown, b"{}").is_err());
+        let disabled = signed("disabled", "disabled-secret", now, b"{}");
+        assert!(auth.check_signature(&disabled, b"{}").is_err());
+
+        let mut missing = signed("ci", "ci-secret", now, b"{}");
+        missing.remove("x-signature");
+        assert_eq!(
+            auth.check_signature(&missing, b"{}"),
+            Err("missing x-signature header".to_owned())
+        );
+    }
+
+    #[test]
+    fn checks_tokens() {
+        let auth = authenticator();
+
+        assert_eq!(auth.check_token("ci-token"), Ok("ci".to_owned()));
+        assert!(auth.check_token("ci-token ").is_err());
+        assert!(auth.check_token("disabled-token").is_err());
+    }
+
+    #[test]
+    fn compares_in_constant_time() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+}
@@ -0,0 +1,230 @@
+use crate::config::Config;
+use crate::request::{Image, ImageRequest, ImageRequestBody};
+use crate::source::{FetchResult, LinkPattern, Source};
+use crate::utils::ResultExtension;
+use act_zero::{Actor, ActorResult, Produces};
+use futures::future::join_all;
+use serde::Deserialize;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+struct ResolvedHandle {
+    did: String,
+}
+
+#[derive(Deserialize)]
+struct PostThread {
+    thread: Thread,
+}
+
+#[derive(Deserialize)]
+struct Thread {
+    /// Missing for deleted or blocked posts.
+    post: Option<PostView>,
+}
+
+#[derive(Deserialize)]
+struct PostView {
+    author: Author,
+    record: PostRecord,
+    embed: Option<Embed>,
+}
+
+#[derive(Deserialize)]
+struct Author {
+    handle: String,
+}
+
+#[derive(Deserialize)]
+struct PostRecord {
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "$type")]
+enum Embed {
+    #[serde(rename = "app.bsky.embed.images#view")]
+    Images { images: Vec<ImageView> },
+    /// Quote post with images attached.
+    #[serde(rename = "app.bsky.embed.recordWithMedia#view")]
+    RecordWithMedia { media: Box<Embed> },
+    #[serde(other)]
+    Other,
+}
+
+impl Embed {
+    fn images(&self) -> &[ImageView] {
+        match self {
+            Embed::Images { images } => images,
+            Embed::RecordWithMedia { media } => media.images(),
+            Embed::Other => &[],
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ImageView {
+    fullsize: String,
+    #[serde(default)]
+    alt: String,
+}
+
+/// Receives posts from Bluesky through an AppView.
+pub struct BlueskyReceiveActor {
+    client: reqwest::Client,
+    appview_url: String,
+}
+
+impl Actor for BlueskyReceiveActor {}
+
+impl BlueskyReceiveActor {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            appview_url: config.bluesky_appview_url.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    async fn xrpc<T: serde::de::DeserializeOwned>(
+        &self,
+        method: &str,
+        query: &[(&str, &str)],
+    ) -> anyhow::Result<T> {
+        Ok(self
+            .client
+            .get(format!("{}/xrpc/{}", self.appview_url, method))
+            .query(query)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    /// Post uris need a DID, profile links usually carry a handle.
+    async fn resolve_did(&self, actor: &str) -> anyhow::Result<String> {
+        if actor.starts_with("did:") {
+            return Ok(actor.to_owned());
+        }
+
+        let resolved: ResolvedHandle = self
+            .xrpc("com.atproto.identity.resolveHandle", &[("handle", actor)])
+            .await
+            .on_error(|_| log::error!("Error on resolve handle {}", actor))?;
+        Ok(resolved.did)
+    }
+
+    async fn download(&self, image: &ImageView) -> anyhow::Result<Image> {
+        let data = self
+            .client
+            .get(&image.fullsize)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+            .on_error(|_| log::error!("Error on download image"))?;
+
+        // Image urls end with `<cid>@<format>`
+        let name = image.fullsize.rsplit('/').next().unwrap();
+        let filename = match name.split_once('@') {
+            Some((cid, "jpeg")) => format!("{}.jpg", cid),
+            Some((cid, format)) => format!("{}.{}", cid, format),
+            None => name.to_owned(),
+        };
+
+        let mut downloaded = Image::new(filename, data.as_ref().into());
+        downloaded.alt = Some(image.alt.trim().to_owned()).filter(|alt| !alt.is_empty());
+        Ok(downloaded)
+    }
+
+    async fn receive_post(&mut self, actor: &str, rkey: &str, url: String) -> FetchResult {
+        log::info!("Start bluesky process {}/{}", actor, rkey);
+
+        let did = self.resolve_did(actor).await?;
+        let uri = format!("at://{}/app.bsky.feed.post/{}", did, rkey);
+        let thread: PostThread = self
+            .xrpc(
+                "app.bsky.feed.getPostThread",
+                &[("uri", uri.as_str()), ("depth", "0"), ("parentHeight", "0")],
+            )
+            .await
+            .on_error(|_| log::error!("Error on request post"))?;
+        let post = thread
+            .thread
+            .post
+            .ok_or_else(|| anyhow::anyhow!("Post {} is not available", uri))?;
+
+        let views = post.embed.as_ref().map(Embed::images).unwrap_or_default();
+        let images: Vec<_> = join_all(views.iter().map(|image| self.download(image)))
+            .await
+            .into_iter()
+            .filter_map(|r| r.on_error(|e| log::error!("{}", e)).ok())
+            .collect();
+
+        log::info!("Downloaded {} images", images.len());
+
+        let body = ImageRequestBody::from_images(images)
+            .ok_or_else(|| anyhow::anyhow!("Post {} has no images", uri))?;
+        let mut request = ImageRequest::new(url, body);
+        request.caption = Some(
+            Some(post.record.text.trim())
+                .filter(|text| !text.is_empty())
+                .map(|text| format!("{}\n\n@{}", text, post.author.handle))
+                .unwrap_or_else(|| format!("@{}", post.author.handle)),
+        );
+        Ok(request)
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for BlueskyReceiveActor {
+    fn name(&self) -> &str {
+        "bluesky"
+    }
+
+    fn link_patterns(&self) -> Vec<LinkPattern> {
+        vec![LinkPattern::new(
+            r"https?://(?:www\.)?bsky\.app/profile/(?P<actor>[\w.:-]+)/post/(?P<rkey>\w+)",
+            "https://bsky.app/profile/$actor/post/$rkey",
+        )]
+    }
+
+    async fn fetch(&mut self, url: String) -> ActorResult<FetchResult> {
+        let post = url
+            .strip_prefix("https://bsky.app/profile/")
+            .and_then(|path| path.split_once("/post/"))
+            .map(|(actor, rkey)| (actor.to_owned(), rkey.to_owned()));
+
+        Produces::ok(match post {
+            Some((actor, rkey)) => self.receive_post(&actor, &rkey, url).await,
+            None => Err(anyhow::anyhow!("{} is not a bluesky post", url)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::source::canonical_links;
+
+    #[test]
+    fn recognizes_posts() {
+        let source = BlueskyReceiveActor::new(test_config(&[]));
+
+        assert_eq!(
+            canonical_links(
+                &source,
+                "https://bsky.app/profile/artist.bsky.social/post/3kabc \
+                 https://bsky.app/profile/did:plc:abc123/post/3kdef"
+            ),
+            vec![
+                "https://bsky.app/profile/artist.bsky.social/post/3kabc",
+                "https://bsky.app/profile/did:plc:abc123/post/3kdef",
+            ]
+        );
+        assert!(canonical_links(&source, "https://bsky.app/profile/artist.bsky.social").is_empty());
+    }
+}
@@ -1,4 +1,5 @@
-use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
 use std::sync::Arc;
 use teloxide_core::prelude::ChatId;
 
@@ -8,9 +9,278 @@ pub struct Config {
     pub pixiv_refresh: String,
     pub telegram_target: ChatId,
     pub telegram_host: Option<String>,
+    /// Users whose private messages to the bot are ingested, the bot does not poll when empty.
+    #[serde(default)]
+    pub telegram_allowed_users: Vec<u64>,
     pub vk_bot_token: String,
     pub vk_target: i64,
+    /// String the VK Callback API expects back on confirmation, enables `/vk/callback`.
+    pub vk_callback_confirmation: Option<String>,
+    /// Secret key of the callback server, required for `/vk/callback`.
+    pub vk_callback_secret: Option<String>,
+    /// Users or conversations whose messages to the community are ingested.
+    #[serde(default)]
+    pub vk_allowed_peers: Vec<i64>,
     pub discord_webhook: Option<String>,
+    /// Hex encoded key of the Discord application, enables `/discord/interactions`.
+    pub discord_public_key: Option<String>,
+    pub discord_application_id: Option<u64>,
+    /// Used to register the slash commands and to answer interactions.
+    pub discord_bot_token: Option<String>,
+    /// Users allowed to run the slash commands.
+    #[serde(default)]
+    pub discord_allowed_users: Vec<u64>,
+    /// Sites running the Gelbooru engine, as a JSON list. Only gelbooru.com when not set.
+    #[serde(
+        default = "default_gelbooru_instances",
+        deserialize_with = "from_json"
+    )]
+    pub gelbooru_instances: Vec<GelbooruInstance>,
+    /// Sites running Moebooru, as a JSON list. yande.re and Konachan when not set.
+    #[serde(
+        default = "default_moebooru_instances",
+        deserialize_with = "from_json"
+    )]
+    pub moebooru_instances: Vec<MoebooruInstance>,
+    pub danbooru_login: Option<String>,
+    pub danbooru_api_key: Option<String>,
+    /// Also names the bot maintainer in the User-Agent, as e621 asks.
+    pub e621_login: Option<String>,
+    pub e621_api_key: Option<String>,
+    /// fxtwitter or vxtwitter compatible api used to read tweets.
+    #[serde(default = "default_twitter_api_url")]
+    pub twitter_api_url: String,
+    #[serde(default = "default_reddit_base_url")]
+    pub reddit_base_url: String,
+    /// Bluesky AppView used to read posts.
+    #[serde(default = "default_bluesky_appview_url")]
+    pub bluesky_appview_url: String,
+    #[serde(default = "default_artstation_base_url")]
+    pub artstation_base_url: String,
+    #[serde(default = "default_deviantart_oembed_url")]
+    pub deviantart_oembed_url: String,
+    /// Site and api used when oEmbed only gives a preview.
+    #[serde(default = "default_deviantart_base_url")]
+    pub deviantart_base_url: String,
+    /// OAuth client credentials, the api is not used without them.
+    pub deviantart_client_id: Option<String>,
+    pub deviantart_client_secret: Option<String>,
+    /// OAuth consumer key of a Tumblr application, enables the Tumblr source.
+    pub tumblr_api_key: Option<String>,
+    #[serde(default = "default_tumblr_api_url")]
+    pub tumblr_api_url: String,
+    /// Client ID of an Imgur application, enables the Imgur source.
+    pub imgur_client_id: Option<String>,
+    #[serde(default = "default_imgur_api_url")]
+    pub imgur_api_url: String,
+    /// Fetches any other link as an image or through the OpenGraph image of the page.
+    #[serde(default)]
+    pub generic_enabled: bool,
+    /// Domains the generic source is limited to, any domain when empty. Subdomains included.
+    #[serde(default)]
+    pub generic_allowed_domains: Vec<String>,
+    /// Domains the generic source never fetches. Subdomains included.
+    #[serde(default)]
+    pub generic_denied_domains: Vec<String>,
+    /// The generic source gives up on responses larger than this many bytes.
+    #[serde(default = "default_generic_max_size")]
+    pub generic_max_size: u64,
+    /// Sites scraped by declarative definitions, as a JSON list.
+    #[serde(default, deserialize_with = "from_json")]
+    pub scrapers: Vec<ScraperDefinition>,
+    /// Booru originals larger than this many bytes are replaced by their resized version.
+    #[serde(default = "default_large_file_threshold")]
+    pub large_file_threshold: u64,
+    #[serde(default = "default_max_running_jobs")]
+    pub max_running_jobs: usize,
+    #[serde(default = "default_job_history")]
+    pub job_history: usize,
+    /// Clients allowed to use the ingestion api, as a JSON list.
+    /// Every request is rejected when the list is empty, unless `api_auth_disabled` is set.
+    #[serde(default, deserialize_with = "from_json")]
+    pub clients: Vec<ClientConfig>,
+    /// Opens the ingestion api to everyone.
+    #[serde(default)]
+    pub api_auth_disabled: bool,
+    /// Ingestion requests a client can make at once.
+    #[serde(default = "default_rate_limit_burst")]
+    pub rate_limit_burst: u32,
+    /// Ingestion requests a client gets back per minute.
+    #[serde(default = "default_rate_limit_per_minute")]
+    pub rate_limit_per_minute: u32,
+    /// Ingestion requests a client can make per UTC day.
+    pub daily_quota: Option<u64>,
+    /// Largest accepted request body, in bytes.
+    #[serde(default = "default_max_upload_size")]
+    pub max_upload_size: usize,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ClientConfig {
+    pub name: String,
+    /// Accepted as `Authorization: Bearer <token>`.
+    pub token: Option<String>,
+    /// Key for HMAC-SHA256 request signatures.
+    pub hmac_secret: Option<String>,
+    #[serde(default)]
+    pub disabled: bool,
+    pub rate_limit_burst: Option<u32>,
+    pub rate_limit_per_minute: Option<u32>,
+    pub daily_quota: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GelbooruInstance {
+    /// Name of the source, shown on jobs.
+    pub name: String,
+    pub base_url: String,
+    /// Regex for the host of post links, the host of `base_url` when not set.
+    pub host: Option<String>,
+    pub api_key: Option<String>,
+    pub user_id: Option<String>,
+}
+
+fn default_gelbooru_instances() -> Vec<GelbooruInstance> {
+    vec![GelbooruInstance {
+        name: "gelbooru".to_owned(),
+        base_url: "https://gelbooru.com".to_owned(),
+        host: None,
+        api_key: None,
+        user_id: None,
+    }]
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MoebooruInstance {
+    /// Name of the source, shown on jobs.
+    pub name: String,
+    pub base_url: String,
+    /// Regex for the host of post and pool links, the host of `base_url` when not set.
+    pub host: Option<String>,
+}
+
+fn default_moebooru_instances() -> Vec<MoebooruInstance> {
+    vec![
+        MoebooruInstance {
+            name: "yandere".to_owned(),
+            base_url: "https://yande.re".to_owned(),
+            host: None,
+        },
+        MoebooruInstance {
+            name: "konachan".to_owned(),
+            base_url: "https://konachan.com".to_owned(),
+            host: Some(r"konachan\.(?:com|net)".to_owned()),
+        },
+    ]
+}
+
+fn default_twitter_api_url() -> String {
+    "https://api.fxtwitter.com".to_owned()
+}
+
+fn default_reddit_base_url() -> String {
+    "https://www.reddit.com".to_owned()
+}
+
+fn default_bluesky_appview_url() -> String {
+    "https://public.api.bsky.app".to_owned()
+}
+
+fn default_artstation_base_url() -> String {
+    "https://www.artstation.com".to_owned()
+}
+
+fn default_deviantart_oembed_url() -> String {
+    "https://backend.deviantart.com/oembed".to_owned()
+}
+
+fn default_deviantart_base_url() -> String {
+    "https://www.deviantart.com".to_owned()
+}
+
+fn default_tumblr_api_url() -> String {
+    "https://api.tumblr.com".to_owned()
+}
+
+fn default_imgur_api_url() -> String {
+    "https://api.imgur.com".to_owned()
+}
+
+fn default_generic_max_size() -> u64 {
+    20 * 1024 * 1024
+}
+
+fn default_large_file_threshold() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_max_running_jobs() -> usize {
+    2
+}
+
+fn default_job_history() -> usize {
+    500
+}
+
+fn default_rate_limit_burst() -> u32 {
+    10
+}
+
+fn default_rate_limit_per_minute() -> u32 {
+    30
+}
+
+fn default_max_upload_size() -> usize {
+    50 * 1024 * 1024
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ScraperDefinition {
+    /// Name of the source, shown on jobs.
+    pub name: String,
+    /// Regex for the links of the site. Named groups can be used in `canonical` and `fetch_url`.
+    pub url_pattern: String,
+    /// Link the job is created for, the whole match when not set. Has to match `url_pattern` too.
+    pub canonical: Option<String>,
+    /// Page or api call to download, as in `https://example.com/api/posts/${id}`.
+    pub fetch_url: String,
+    pub format: ScraperFormat,
+    /// Expressions for the fields of the post: JSONPath for `json`, CSS selectors for `html`.
+    /// A selector takes the text of the elements, or an attribute when it ends with `@name`.
+    pub images: String,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub tags: Option<String>,
+    /// Saved responses checked by `check-scrapers`.
+    #[serde(default)]
+    pub fixtures: Vec<ScraperFixture>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScraperFormat {
+    Json,
+    Html,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ScraperFixture {
+    /// Link the response belongs to, it has to be recognized by the definition.
+    pub url: String,
+    /// Path of the saved response of `fetch_url`.
+    pub file: String,
+    /// Expected results, only compared when set.
+    pub images: Option<usize>,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub tags: Option<Vec<String>>,
+}
+
+/// Only the scraper definitions, for checking them without the rest of the settings.
+#[derive(Deserialize)]
+struct ScraperConfig {
+    #[serde(default, deserialize_with = "from_json")]
+    scrapers: Vec<ScraperDefinition>,
 }
 
 pub fn get_config() -> Arc<Config> {
@@ -20,3 +290,42 @@ pub fn get_config() -> Arc<Config> {
             .expect("Error on load config"),
     )
 }
+
+pub fn get_scrapers() -> Vec<ScraperDefinition> {
+    envy::prefixed("NS_")
+        .from_env::<ScraperConfig>()
+        .expect("Error on load scrapers")
+        .scrapers
+}
+
+/// Config with only the required settings and `vars` set, named without the `NS_` prefix.
+#[cfg(test)]
+pub fn test_config(vars: &[(&str, &str)]) -> Arc<Config> {
+    let required = [
+        ("TELEGRAM_TOKEN", "token"),
+        ("PIXIV_REFRESH", "refresh"),
+        ("TELEGRAM_TARGET", "1"),
+        ("VK_BOT_TOKEN", "token"),
+        ("VK_TARGET", "1"),
+    ];
+    let vars = required
+        .iter()
+        .chain(vars)
+        .map(|(name, value)| (format!("NS_{}", name), value.to_string()));
+
+    Arc::new(
+        envy::prefixed("NS_")
+            .from_iter(vars)
+            .expect("Error on load config"),
+    )
+}
+
+/// Parses structured settings that are passed as JSON inside a single variable.
+fn from_json<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let raw = String::deserialize(deserializer)?;
+    serde_json::from_str(&raw).map_err(serde::de::Error::custom)
+}
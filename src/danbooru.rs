@@ -0,0 +1,202 @@
+use crate::config::Config;
+use crate::request::{Image, ImageRequest, ImageRequestBody};
+use crate::source::{FetchResult, LinkPattern, Source};
+use crate::utils::ResultExtension;
+use act_zero::{Actor, ActorResult, Produces};
+use futures::future::join_all;
+use itertools::Itertools;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::sync::Arc;
+
+const BASE_URL: &str = "https://danbooru.donmai.us";
+
+/// Largest number of posts taken from a pool, which is also the page limit of the api.
+const MAX_POOL_POSTS: usize = 200;
+
+#[derive(Deserialize)]
+struct Post {
+    id: u64,
+    /// Missing for posts hidden from the current user.
+    file_url: Option<String>,
+    large_file_url: Option<String>,
+    #[serde(default)]
+    file_size: u64,
+}
+
+#[derive(Deserialize)]
+struct Pool {
+    post_ids: Vec<u64>,
+}
+
+pub struct DanbooruReceiveActor {
+    client: reqwest::Client,
+    /// Login and api key.
+    credentials: Option<(String, String)>,
+    large_file_threshold: u64,
+}
+
+impl Actor for DanbooruReceiveActor {}
+
+impl DanbooruReceiveActor {
+    pub fn new(config: Arc<Config>) -> Self {
+        // Danbooru rejects requests without a user agent
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("heroku_bot/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .expect("Error on build client");
+
+        let credentials = config
+            .danbooru_login
+            .clone()
+            .zip(config.danbooru_api_key.clone());
+
+        Self {
+            client,
+            credentials,
+            large_file_threshold: config.large_file_threshold,
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> anyhow::Result<T> {
+        let mut request = self.client.get(format!("{}/{}", BASE_URL, path));
+        if let Some((login, api_key)) = &self.credentials {
+            request = request.basic_auth(login, Some(api_key));
+        }
+
+        Ok(request.send().await?.error_for_status()?.json().await?)
+    }
+
+    async fn download(&self, post: &Post) -> anyhow::Result<Image> {
+        let too_large = post.file_size > self.large_file_threshold;
+        let url = match (&post.file_url, &post.large_file_url) {
+            (Some(_), Some(large)) if too_large => large,
+            (Some(url), _) => url,
+            (None, _) => anyhow::bail!("Post {} has no file available", post.id),
+        };
+
+        let data = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+            .on_error(|_| log::error!("Error on download image"))?;
+
+        Ok(Image::new(
+            url.rsplit('/').next().unwrap().to_string(),
+            data.as_ref().into(),
+        ))
+    }
+
+    async fn receive_post(&mut self, id: u64, url: String) -> FetchResult {
+        log::info!("Start danbooru process post {}", id);
+
+        let post: Post = self
+            .get(&format!("posts/{}.json", id))
+            .await
+            .on_error(|_| log::error!("Error on request post"))?;
+        let image = self.download(&post).await?;
+
+        Ok(ImageRequest::new(url, ImageRequestBody::SingleImage { image }))
+    }
+
+    async fn receive_pool(&mut self, id: u64, url: String) -> FetchResult {
+        log::info!("Start danbooru process pool {}", id);
+
+        let pool: Pool = self
+            .get(&format!("pools/{}.json", id))
+            .await
+            .on_error(|_| log::error!("Error on request pool"))?;
+        if pool.post_ids.len() > MAX_POOL_POSTS {
+            log::warn!(
+                "Pool {} has {} posts, only the first {} are taken",
+                id,
+                pool.post_ids.len(),
+                MAX_POOL_POSTS
+            );
+        }
+
+        let ids: Vec<_> = pool.post_ids.into_iter().take(MAX_POOL_POSTS).collect();
+        let posts: Vec<Post> = self
+            .get(&format!(
+                "posts.json?tags=id:{}&limit={}",
+                ids.iter().join(","),
+                MAX_POOL_POSTS
+            ))
+            .await
+            .on_error(|_| log::error!("Error on request pool posts"))?;
+
+        // The api returns posts newest first, keep the pool order instead
+        let posts = ids
+            .iter()
+            .filter_map(|id| posts.iter().find(|post| post.id == *id));
+        let images: Vec<_> = join_all(posts.map(|post| self.download(post)))
+            .await
+            .into_iter()
+            .filter_map(|r| r.on_error(|e| log::error!("{}", e)).ok())
+            .collect();
+
+        log::info!("Downloaded {} images", images.len());
+
+        let body = ImageRequestBody::from_images(images)
+            .ok_or_else(|| anyhow::anyhow!("Pool {} has no downloadable posts", id))?;
+        Ok(ImageRequest::new(url, body))
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for DanbooruReceiveActor {
+    fn name(&self) -> &str {
+        "danbooru"
+    }
+
+    fn link_patterns(&self) -> Vec<LinkPattern> {
+        vec![
+            LinkPattern::new(
+                r"https?://danbooru\.donmai\.us/posts/(?P<id>\d+)",
+                "https://danbooru.donmai.us/posts/$id",
+            ),
+            LinkPattern::new(
+                r"https?://danbooru\.donmai\.us/pools/(?P<id>\d+)",
+                "https://danbooru.donmai.us/pools/$id",
+            ),
+        ]
+    }
+
+    async fn fetch(&mut self, url: String) -> ActorResult<FetchResult> {
+        let link = url
+            .strip_prefix(BASE_URL)
+            .and_then(|path| path.trim_start_matches('/').split_once('/'))
+            .and_then(|(kind, id)| Some((kind.to_owned(), id.parse().ok()?)));
+
+        Produces::ok(match link {
+            Some((kind, id)) if kind == "posts" => self.receive_post(id, url).await,
+            Some((kind, id)) if kind == "pools" => self.receive_pool(id, url).await,
+            _ => Err(anyhow::anyhow!("{} is not a danbooru post or pool", url)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::source::canonical_links;
+
+    #[test]
+    fn recognizes_posts_and_pools() {
+        let source = DanbooruReceiveActor::new(test_config(&[]));
+
+        assert_eq!(
+            canonical_links(
+                &source,
+                "https://danbooru.donmai.us/posts/123?q=sky https://danbooru.donmai.us/pools/45"
+            ),
+            vec!["https://danbooru.donmai.us/posts/123", "https://danbooru.donmai.us/pools/45"]
+        );
+        assert!(canonical_links(&source, "https://danbooru.donmai.us/posts?tags=sky").is_empty());
+    }
+}
@@ -0,0 +1,265 @@
+use crate::config::Config;
+use crate::request::{Image, ImageRequest, ImageRequestBody};
+use crate::source::{FetchResult, LinkPattern, Source};
+use crate::utils::ResultExtension;
+use act_zero::{Actor, ActorResult, Produces};
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Deserialize)]
+struct OEmbed {
+    /// `photo` for images, `rich` or `link` for literature and the like.
+    #[serde(rename = "type")]
+    kind: String,
+    url: Option<String>,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    author_name: String,
+    /// `nonadult` or `adult`.
+    safety: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Token {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Deserialize)]
+struct Download {
+    src: String,
+}
+
+#[derive(Deserialize)]
+struct Deviation {
+    content: Option<Content>,
+}
+
+#[derive(Deserialize)]
+struct Content {
+    src: String,
+}
+
+/// Receives deviations from DeviantArt.
+pub struct DeviantArtReceiveActor {
+    client: reqwest::Client,
+    oembed_url: String,
+    base_url: String,
+    credentials: Option<(String, String)>,
+    /// Access token and when it expires.
+    token: Option<(String, Instant)>,
+}
+
+impl Actor for DeviantArtReceiveActor {}
+
+impl DeviantArtReceiveActor {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            oembed_url: config.deviantart_oembed_url.clone(),
+            base_url: config.deviantart_base_url.trim_end_matches('/').to_owned(),
+            credentials: config
+                .deviantart_client_id
+                .clone()
+                .zip(config.deviantart_client_secret.clone()),
+            token: None,
+        }
+    }
+
+    async fn access_token(&mut self) -> anyhow::Result<String> {
+        if let Some((token, expires)) = &self.token {
+            if Instant::now() < *expires {
+                return Ok(token.clone());
+            }
+        }
+
+        let (client_id, client_secret) = self
+            .credentials
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("DeviantArt api credentials are not configured"))?;
+        let token: Token = self
+            .client
+            .post(format!("{}/oauth2/token", self.base_url))
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", client_id),
+                ("client_secret", client_secret),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .on_error(|_| log::error!("Error on request DeviantArt token"))?;
+
+        // Renew a minute early to not use a token that expires on the way
+        let lifetime = Duration::from_secs(token.expires_in.saturating_sub(60));
+        self.token = Some((token.access_token.clone(), Instant::now() + lifetime));
+        Ok(token.access_token)
+    }
+
+    /// Original of the deviation through the api: the download when the artist allows it,
+    /// the largest displayed version otherwise.
+    async fn original_url(&mut self, url: &str) -> anyhow::Result<String> {
+        let token = self.access_token().await?;
+
+        // The api only knows deviations by uuid, which the page carries in its app link
+        let path = url.trim_start_matches("https://www.deviantart.com");
+        let page = self
+            .client
+            .get(format!("{}{}", self.base_url, path))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        static APP_LINK: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"DeviantArt://deviation/([0-9A-Fa-f-]{36})").unwrap());
+        let uuid = APP_LINK
+            .captures(&page)
+            .map(|captures| captures[1].to_owned())
+            .ok_or_else(|| anyhow::anyhow!("No deviation id found on {}", url))?;
+
+        let download = self
+            .client
+            .get(format!("{}/api/v1/oauth2/deviation/download/{}", self.base_url, uuid))
+            .bearer_auth(&token)
+            .send()
+            .await?;
+        if download.status().is_success() {
+            return Ok(download.json::<Download>().await?.src);
+        }
+
+        let deviation: Deviation = self
+            .client
+            .get(format!("{}/api/v1/oauth2/deviation/{}", self.base_url, uuid))
+            .bearer_auth(&token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        deviation
+            .content
+            .map(|content| content.src)
+            .ok_or_else(|| anyhow::anyhow!("Deviation {} has no image", uuid))
+    }
+
+    async fn receive_deviation(&mut self, url: String) -> FetchResult {
+        log::info!("Start deviantart process {}", url);
+
+        let oembed: OEmbed = self
+            .client
+            .get(&self.oembed_url)
+            .query(&[("url", url.as_str())])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .on_error(|_| log::error!("Error on request oembed"))?;
+
+        let mut image_url = match (oembed.kind.as_str(), oembed.url) {
+            ("photo", Some(image_url)) => image_url,
+            _ => anyhow::bail!("{} is not an image", url),
+        };
+        if is_preview(&image_url) {
+            if self.credentials.is_some() {
+                if let Ok(original) = self
+                    .original_url(&url)
+                    .await
+                    .on_error(|e| log::error!("Error on get original of {}, send the preview: {:#}", url, e))
+                {
+                    image_url = original;
+                }
+            } else {
+                log::warn!("Only a preview of {} is available without api credentials", url);
+            }
+        }
+
+        let data = self
+            .client
+            .get(&image_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+            .on_error(|_| log::error!("Error on download image"))?;
+
+        let path = image_url.split('?').next().unwrap();
+        let filename = path.rsplit('/').next().unwrap().to_string();
+        let image = Image::new(filename, data.as_ref().into());
+
+        let mut request = ImageRequest::new(url, ImageRequestBody::SingleImage { image });
+        let caption = [oembed.title.trim(), oembed.author_name.trim()]
+            .iter()
+            .filter(|text| !text.is_empty())
+            .join("\n\n");
+        request.caption = Some(caption).filter(|caption| !caption.is_empty());
+        request.nsfw = oembed.safety.as_deref() == Some("adult");
+        Ok(request)
+    }
+}
+
+/// Images resized by the DeviantArt CDN carry the transformation in the path.
+fn is_preview(url: &str) -> bool {
+    ["/v1/fill/", "/v1/fit/", "/v1/crop/"]
+        .iter()
+        .any(|transformation| url.contains(transformation))
+}
+
+#[async_trait::async_trait]
+impl Source for DeviantArtReceiveActor {
+    fn name(&self) -> &str {
+        "deviantart"
+    }
+
+    /// Recognizes deviation links, also on the old artist subdomains.
+    fn link_patterns(&self) -> Vec<LinkPattern> {
+        vec![
+            LinkPattern::new(
+                r"https?://(?:www\.)?deviantart\.com/(?P<user>[\w-]+)/art/(?P<slug>[\w-]*\d+)",
+                "https://www.deviantart.com/$user/art/$slug",
+            ),
+            LinkPattern::new(
+                r"https?://(?P<user>[\w-]+)\.deviantart\.com/art/(?P<slug>[\w-]*\d+)",
+                "https://www.deviantart.com/$user/art/$slug",
+            ),
+        ]
+    }
+
+    async fn fetch(&mut self, url: String) -> ActorResult<FetchResult> {
+        Produces::ok(self.receive_deviation(url).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::source::canonical_links;
+
+    #[test]
+    fn recognizes_deviations() {
+        let source = DeviantArtReceiveActor::new(test_config(&[]));
+
+        assert_eq!(
+            canonical_links(
+                &source,
+                "https://www.deviantart.com/artist/art/Some-Title-123456 \
+                 https://other-artist.deviantart.com/art/654321"
+            ),
+            vec![
+                "https://www.deviantart.com/artist/art/Some-Title-123456",
+                "https://www.deviantart.com/other-artist/art/654321",
+            ]
+        );
+        assert!(canonical_links(&source, "https://www.deviantart.com/artist/gallery").is_empty());
+    }
+}
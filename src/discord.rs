@@ -2,7 +2,7 @@ use std::sync::Arc;
 use act_zero::{Actor, ActorResult, Produces};
 use serenity::builder::{CreateAttachment, ExecuteWebhook};
 use crate::config::Config;
-use crate::request::{ImageRequest, ImageRequestBody, ImageSender};
+use crate::request::{DeliveryResult, ImageRequest, ImageRequestBody, ImageSender};
 
 pub struct DiscordWebhookActor {
     config: Arc<Config>,
@@ -25,9 +25,8 @@ impl Actor for DiscordWebhookActor {
     }
 }
 
-#[async_trait::async_trait]
-impl ImageSender for DiscordWebhookActor {
-    async fn handle_request(&mut self, request: Arc<ImageRequest>) -> ActorResult<()> {
+impl DiscordWebhookActor {
+    async fn send_request(&self, request: &ImageRequest) -> anyhow::Result<()> {
         let webhook = self.config.discord_webhook.as_ref().unwrap();
 
         log::info!("Uploading image to Discord from {}", request.source);
@@ -39,21 +38,46 @@ impl ImageSender for DiscordWebhookActor {
 
         let webhook = self.http.get_webhook_from_url(webhook).await?;
 
-        for image in images {
-            let file_name = image.filename.clone();
-            let image = image::load_from_memory(image.data.as_ref()).expect("Error on load image");
-            let mut buffer = Vec::new();
-            let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, 90);
-            encoder.encode_image(&image)?;
-
-            webhook.execute(&self.http, false, ExecuteWebhook::new()
-                .add_file(
-                    CreateAttachment::bytes(buffer.as_slice(), format!("{file_name}.jpg").as_str())
-                ))
-                .await?;
+        for (i, image) in images.into_iter().enumerate() {
+            // Discord blurs attachments whose name starts with SPOILER_
+            let file_name = if request.nsfw {
+                format!("SPOILER_{}", image.filename)
+            } else {
+                image.filename.clone()
+            };
+            // Discord plays GIFs inline, so animations stay GIFs
+            let attachment = if image.is_video() || image.is_animation() {
+                CreateAttachment::bytes(image.data.as_ref(), file_name.as_str())
+            } else {
+                let image = image::load_from_memory(image.data.as_ref())
+                    .map_err(|e| anyhow::anyhow!("Error on load image {}: {}", image.filename, e))?;
+                let mut buffer = Vec::new();
+                let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, 90);
+                encoder.encode_image(&image)?;
+                CreateAttachment::bytes(buffer.as_slice(), format!("{file_name}.jpg").as_str())
+            };
+
+            let attachment = match &image.alt {
+                Some(alt) => attachment.description(alt.chars().take(1024).collect::<String>()),
+                None => attachment,
+            };
+
+            let mut message = ExecuteWebhook::new().add_file(attachment);
+            if i == 0 {
+                message = message.content(request.text());
+            }
+
+            webhook.execute(&self.http, false, message).await?;
         }
 
         log::info!("Uploaded image to Discord from {}", request.source);
-        Produces::ok(())
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl ImageSender for DiscordWebhookActor {
+    async fn handle_request(&mut self, request: Arc<ImageRequest>) -> ActorResult<DeliveryResult> {
+        Produces::ok(self.send_request(&request).await)
     }
 }
@@ -0,0 +1,441 @@
+use crate::config::Config;
+use crate::jobs::{ingest_text, JobId, JobManagerActor};
+use crate::source::SourceRegistry;
+use crate::utils::ResultExtension;
+use act_zero::{call, Addr};
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::{Json, Router};
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use serenity::all::{ApplicationId, Command, CommandOptionType};
+use serenity::builder::{CreateCommand, CreateCommandOption};
+use serenity::http::Http;
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const PING: u8 = 1;
+const APPLICATION_COMMAND: u8 = 2;
+
+const PONG: u8 = 1;
+const CHANNEL_MESSAGE: u8 = 4;
+const DEFERRED_CHANNEL_MESSAGE: u8 = 5;
+
+/// Message only visible to the user who ran the command.
+const EPHEMERAL: u64 = 1 << 6;
+
+/// How far the `X-Signature-Timestamp` of an interaction may be from the server time, in seconds.
+const SIGNATURE_TOLERANCE: i64 = 300;
+
+struct InteractionsState {
+    public_key: VerifyingKey,
+    http: Arc<Http>,
+    allowed_users: Vec<u64>,
+    sources: Arc<SourceRegistry>,
+    jobs: Addr<JobManagerActor>,
+}
+
+/// Route for Discord interactions, only present when the application public key is configured.
+///
+/// Handles the `/share`, `/status` and `/retry` slash commands of allowed users.
+pub fn router(
+    config: &Config,
+    sources: Arc<SourceRegistry>,
+    jobs: Addr<JobManagerActor>,
+) -> Router {
+    let public_key = match &config.discord_public_key {
+        Some(public_key) => parse_public_key(public_key).expect("Invalid Discord public key"),
+        None => return Router::new(),
+    };
+
+    let state = InteractionsState {
+        public_key,
+        http: Arc::new(build_http(config).expect("Error on create Discord client")),
+        allowed_users: config.discord_allowed_users.clone(),
+        sources,
+        jobs,
+    };
+
+    Router::new()
+        .route("/discord/interactions", post(interactions))
+        .with_state(Arc::new(state))
+}
+
+/// Overwrites the global slash commands of the application.
+pub async fn register_commands(config: &Config) -> anyhow::Result<()> {
+    let http = build_http(config)?;
+
+    let job = || {
+        CreateCommandOption::new(CommandOptionType::Integer, "job", "Job id")
+            .min_int_value(1)
+            .required(true)
+    };
+    let commands = vec![
+        CreateCommand::new("share")
+            .description("Share images from a link")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "url", "Link to the post")
+                    .required(true),
+            ),
+        CreateCommand::new("status")
+            .description("Show the state of a job")
+            .add_option(job()),
+        CreateCommand::new("retry")
+            .description("Run a finished job again")
+            .add_option(job()),
+    ];
+
+    let commands = Command::set_global_commands(&http, commands).await?;
+    for command in commands {
+        log::info!("Registered Discord command /{}", command.name);
+    }
+    Ok(())
+}
+
+fn build_http(config: &Config) -> anyhow::Result<Http> {
+    let token = config
+        .discord_bot_token
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("NS_DISCORD_BOT_TOKEN is not set"))?;
+    let application_id = config
+        .discord_application_id
+        .ok_or_else(|| anyhow::anyhow!("NS_DISCORD_APPLICATION_ID is not set"))?;
+
+    let http = Http::new(token);
+    http.set_application_id(ApplicationId::new(application_id));
+    Ok(http)
+}
+
+fn parse_public_key(public_key: &str) -> anyhow::Result<VerifyingKey> {
+    let bytes: [u8; 32] = hex::decode(public_key)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("public key must be 32 bytes"))?;
+    Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+#[derive(Deserialize)]
+struct Interaction {
+    #[serde(rename = "type")]
+    kind: u8,
+    token: String,
+    data: Option<CommandData>,
+    /// Set for commands run in a guild.
+    member: Option<Member>,
+    /// Set for commands run in a direct message.
+    user: Option<User>,
+}
+
+#[derive(Deserialize)]
+struct CommandData {
+    name: String,
+    #[serde(default)]
+    options: Vec<CommandDataOption>,
+}
+
+#[derive(Deserialize)]
+struct CommandDataOption {
+    name: String,
+    value: Value,
+}
+
+#[derive(Deserialize)]
+struct Member {
+    user: User,
+}
+
+#[derive(Deserialize)]
+struct User {
+    id: String,
+}
+
+impl Interaction {
+    fn user_id(&self) -> Option<u64> {
+        let user = self
+            .member
+            .as_ref()
+            .map(|member| &member.user)
+            .or(self.user.as_ref())?;
+        user.id.parse().ok()
+    }
+
+    fn option(&self, name: &str) -> Option<&Value> {
+        self.data
+            .as_ref()?
+            .options
+            .iter()
+            .find(|option| option.name == name)
+            .map(|option| &option.value)
+    }
+}
+
+async fn interactions(
+    State(state): State<Arc<InteractionsState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Value>, StatusCode> {
+    if let Err(e) = verify(&state.public_key, &headers, &body) {
+        log::warn!("Rejected Discord interaction: {}", e);
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let interaction = serde_json::from_slice::<Interaction>(&body)
+        .on_error(|e| log::error!("Error on parse Discord interaction: {}", e))
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    match interaction.kind {
+        PING => Ok(Json(json!({ "type": PONG }))),
+        APPLICATION_COMMAND => Ok(Json(handle_command(state, interaction).await)),
+        kind => {
+            log::warn!("Ignore Discord interaction of type {}", kind);
+            Err(StatusCode::BAD_REQUEST)
+        }
+    }
+}
+
+/// Checks the `X-Signature-Ed25519` header against `<timestamp><body>`, signatures of
+/// interactions sent too long ago are rejected so they cannot be replayed.
+fn verify(public_key: &VerifyingKey, headers: &HeaderMap, body: &[u8]) -> anyhow::Result<()> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| anyhow::anyhow!("missing {} header", name))
+    };
+
+    let signature: [u8; 64] = hex::decode(header("x-signature-ed25519")?)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("signature must be 64 bytes"))?;
+    let timestamp = header("x-signature-timestamp")?;
+    let mut message = timestamp.as_bytes().to_vec();
+    message.extend_from_slice(body);
+
+    public_key.verify_strict(&message, &Signature::from_bytes(&signature))?;
+
+    let timestamp: i64 = timestamp
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid timestamp"))?;
+    if (chrono::Utc::now().timestamp() - timestamp).abs() > SIGNATURE_TOLERANCE {
+        anyhow::bail!("timestamp is out of the allowed window");
+    }
+    Ok(())
+}
+
+async fn handle_command(state: Arc<InteractionsState>, interaction: Interaction) -> Value {
+    let user = interaction.user_id().unwrap_or_default();
+    if !state.allowed_users.contains(&user) {
+        log::warn!("Ignore Discord command from not allowed user {}", user);
+        return message("You are not allowed to use this bot", EPHEMERAL);
+    }
+
+    let name = interaction
+        .data
+        .as_ref()
+        .map(|data| data.name.as_str())
+        .unwrap_or_default();
+    let job = interaction.option("job").and_then(Value::as_u64);
+
+    match (name, job) {
+        ("share", _) => {
+            let url = interaction
+                .option("url")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_owned();
+            tokio::spawn(async move {
+                let reply = Reply::new(&state.http, interaction.token);
+                ingest_text(&state.sources, &state.jobs, &url, |text| reply.send(text)).await;
+            });
+            json!({ "type": DEFERRED_CHANNEL_MESSAGE })
+        }
+        ("status", Some(id)) => match call!(state.jobs.get(id)).await {
+            Ok(Some(job)) => message(&job.summary(), 0),
+            Ok(None) => message(&format!("Job {} not found", id), EPHEMERAL),
+            Err(_) => message("Jobs are unavailable", EPHEMERAL),
+        },
+        ("retry", Some(id)) => match call!(state.jobs.retry(id)).await {
+            Ok(Ok(retry)) => {
+                tokio::spawn(async move {
+                    let reply = Reply::new(&state.http, interaction.token);
+                    report_retry(&state.jobs, id, retry, &reply).await;
+                });
+                json!({ "type": DEFERRED_CHANNEL_MESSAGE })
+            }
+            Ok(Err(e)) => message(&e.to_string(), EPHEMERAL),
+            Err(_) => message("Jobs are unavailable", EPHEMERAL),
+        },
+        _ => {
+            log::warn!("Unknown Discord command {}", name);
+            message(&format!("Unknown command {}", name), EPHEMERAL)
+        }
+    }
+}
+
+async fn report_retry(jobs: &Addr<JobManagerActor>, id: JobId, retry: JobId, reply: &Reply<'_>) {
+    reply
+        .send(format!("Accepted job {} to retry job {}", retry, id))
+        .await;
+
+    if let Ok(Some(job)) = call!(jobs.wait(retry)).await {
+        reply.send(job.summary()).await;
+    }
+}
+
+fn message(content: &str, flags: u64) -> Value {
+    json!({
+        "type": CHANNEL_MESSAGE,
+        "data": { "content": content, "flags": flags },
+    })
+}
+
+/// Answers a deferred interaction: the first reply replaces the loading message,
+/// the next ones are sent as follow-up messages.
+struct Reply<'a> {
+    http: &'a Http,
+    token: String,
+    answered: AtomicBool,
+}
+
+impl<'a> Reply<'a> {
+    fn new(http: &'a Http, token: String) -> Self {
+        Self {
+            http,
+            token,
+            answered: AtomicBool::new(false),
+        }
+    }
+
+    async fn send(&self, text: String) {
+        let content = json!({ "content": text });
+        let result = if self.answered.swap(true, Ordering::SeqCst) {
+            self.http
+                .create_followup_message(&self.token, &content, vec![])
+                .await
+        } else {
+            self.http
+                .edit_original_interaction_response(&self.token, &content, vec![])
+                .await
+        };
+
+        let _ = result.on_error(|e| log::error!("Error on reply to Discord interaction: {}", e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use act_zero::runtimes::tokio::spawn_actor;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const ALLOWED_USER: u64 = 42;
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    fn signed(key: &SigningKey, timestamp: i64, body: &[u8]) -> HeaderMap {
+        let mut message = timestamp.to_string().into_bytes();
+        message.extend_from_slice(body);
+        let signature = key.sign(&message);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-signature-ed25519", hex::encode(signature.to_bytes()).parse().unwrap());
+        headers.insert("x-signature-timestamp", timestamp.to_string().parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn accepts_a_valid_signature() {
+        let key = signing_key();
+        let body = br#"{"type":1}"#;
+        let headers = signed(&key, chrono::Utc::now().timestamp(), body);
+
+        assert!(verify(&key.verifying_key(), &headers, body).is_ok());
+    }
+
+    #[test]
+    fn rejects_tampered_and_stale_signatures() {
+        let key = signing_key();
+        let now = chrono::Utc::now().timestamp();
+
+        let headers = signed(&key, now, br#"{"type":1}"#);
+        assert!(verify(&key.verifying_key(), &headers, br#"{"type":2}"#).is_err());
+
+        let other = SigningKey::from_bytes(&[8; 32]);
+        let headers = signed(&other, now, br#"{"type":1}"#);
+        assert!(verify(&key.verifying_key(), &headers, br#"{"type":1}"#).is_err());
+
+        let headers = signed(&key, now - SIGNATURE_TOLERANCE - 1, br#"{"type":1}"#);
+        let error = verify(&key.verifying_key(), &headers, br#"{"type":1}"#).unwrap_err();
+        assert_eq!(error.to_string(), "timestamp is out of the allowed window");
+
+        let mut headers = signed(&key, now, br#"{"type":1}"#);
+        headers.remove("x-signature-ed25519");
+        assert!(verify(&key.verifying_key(), &headers, br#"{"type":1}"#).is_err());
+    }
+
+    fn state() -> Arc<InteractionsState> {
+        Arc::new(InteractionsState {
+            public_key: signing_key().verifying_key(),
+            http: Arc::new(Http::new("token")),
+            allowed_users: vec![ALLOWED_USER],
+            sources: Arc::new(SourceRegistry::new()),
+            jobs: spawn_actor(JobManagerActor::new(Addr::detached(), 1, 10)),
+        })
+    }
+
+    fn command(user: u64, name: &str, options: Value) -> Interaction {
+        serde_json::from_value(json!({
+            "type": APPLICATION_COMMAND,
+            "token": "token",
+            "member": { "user": { "id": user.to_string() } },
+            "data": { "name": name, "options": options },
+        }))
+        .unwrap()
+    }
+
+    fn content(response: &Value) -> &str {
+        response["data"]["content"].as_str().unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn dispatches_commands_of_allowed_users() {
+        let job = json!([{ "name": "job", "value": 5 }]);
+
+        let response = handle_command(state(), command(ALLOWED_USER, "status", job.clone())).await;
+        assert_eq!(content(&response), "Job 5 not found");
+
+        let response = handle_command(state(), command(ALLOWED_USER, "retry", job)).await;
+        assert_eq!(content(&response), "Job 5 not found");
+        assert_eq!(response["data"]["flags"], EPHEMERAL);
+
+        let response = handle_command(state(), command(ALLOWED_USER, "delete", json!([]))).await;
+        assert_eq!(content(&response), "Unknown command delete");
+    }
+
+    #[tokio::test]
+    async fn ignores_commands_of_other_users() {
+        let job = json!([{ "name": "job", "value": 5 }]);
+
+        let response = handle_command(state(), command(7, "status", job)).await;
+        assert_eq!(content(&response), "You are not allowed to use this bot");
+        assert_eq!(response["data"]["flags"], EPHEMERAL);
+    }
+
+    #[tokio::test]
+    async fn answers_pings_only_when_signed() {
+        let state = state();
+        let body = Bytes::from_static(br#"{"type":1,"token":"token"}"#);
+
+        let headers = signed(&signing_key(), chrono::Utc::now().timestamp(), &body);
+        let response = interactions(State(state.clone()), headers, body.clone()).await;
+        assert_eq!(response.unwrap().0, json!({ "type": PONG }));
+
+        let headers = signed(&signing_key(), chrono::Utc::now().timestamp(), b"{}");
+        let response = interactions(State(state), headers, body).await;
+        assert_eq!(response.unwrap_err(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,248 @@
+use crate::config::Config;
+use crate::request::{Image, ImageRequest, ImageRequestBody};
+use crate::source::{FetchResult, LinkPattern, Source};
+use crate::utils::ResultExtension;
+use act_zero::{Actor, ActorResult, Produces};
+use futures::future::join_all;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// e621 allows one api request per second.
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Page limit of the api.
+const MAX_POOL_POSTS: usize = 320;
+
+const VIDEO_EXTENSIONS: [&str; 2] = ["webm", "mp4"];
+
+#[derive(Deserialize)]
+struct PostResponse {
+    post: Post,
+}
+
+#[derive(Deserialize)]
+struct PostsResponse {
+    posts: Vec<Post>,
+}
+
+#[derive(Deserialize)]
+struct Post {
+    id: u64,
+    file: File,
+    sample: Sample,
+}
+
+#[derive(Deserialize)]
+struct File {
+    ext: String,
+    size: u64,
+    /// Hidden for some posts unless logged in.
+    url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Sample {
+    has: bool,
+    url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Pool {
+    post_ids: Vec<u64>,
+}
+
+/// Receives posts and pools from e621 and its safe mirror e926.
+pub struct E621ReceiveActor {
+    client: reqwest::Client,
+    /// Login and api key.
+    credentials: Option<(String, String)>,
+    large_file_threshold: u64,
+    last_request: Option<Instant>,
+}
+
+impl Actor for E621ReceiveActor {}
+
+impl E621ReceiveActor {
+    pub fn new(config: Arc<Config>) -> Self {
+        let user_agent = match &config.e621_login {
+            Some(login) => format!(
+                "heroku_bot/{} (by {} on e621)",
+                env!("CARGO_PKG_VERSION"),
+                login
+            ),
+            None => format!("heroku_bot/{}", env!("CARGO_PKG_VERSION")),
+        };
+        let client = reqwest::Client::builder()
+            .user_agent(user_agent)
+            .build()
+            .expect("Error on build client");
+
+        let credentials = config.e621_login.clone().zip(config.e621_api_key.clone());
+
+        Self {
+            client,
+            credentials,
+            large_file_threshold: config.large_file_threshold,
+            last_request: None,
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&mut self, url: &str) -> anyhow::Result<T> {
+        if let Some(last_request) = self.last_request {
+            let elapsed = last_request.elapsed();
+            if elapsed < REQUEST_INTERVAL {
+                tokio::time::sleep(REQUEST_INTERVAL - elapsed).await;
+            }
+        }
+        self.last_request = Some(Instant::now());
+
+        let mut request = self.client.get(url);
+        if let Some((login, api_key)) = &self.credentials {
+            request = request.basic_auth(login, Some(api_key));
+        }
+
+        Ok(request.send().await?.error_for_status()?.json().await?)
+    }
+
+    async fn download(&self, post: &Post) -> anyhow::Result<Image> {
+        let video = VIDEO_EXTENSIONS.contains(&post.file.ext.as_str());
+        // Samples of videos are still previews, only images are swapped for them
+        let too_large = !video && post.file.size > self.large_file_threshold;
+        let url = match (&post.file.url, &post.sample.url) {
+            (Some(_), Some(sample)) if too_large && post.sample.has => sample,
+            (Some(url), _) => url,
+            (None, _) => anyhow::bail!("Post {} is only available when logged in", post.id),
+        };
+
+        let data = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+            .on_error(|_| log::error!("Error on download image"))?;
+
+        let filename = url.rsplit('/').next().unwrap().to_string();
+        Ok(if video {
+            Image::video(filename, data.as_ref().into())
+        } else {
+            Image::new(filename, data.as_ref().into())
+        })
+    }
+
+    async fn receive_post(&mut self, base: &str, id: u64, url: String) -> FetchResult {
+        log::info!("Start e621 process post {}", id);
+
+        let response: PostResponse = self
+            .get(&format!("{}/posts/{}.json", base, id))
+            .await
+            .on_error(|_| log::error!("Error on request post"))?;
+        let image = self.download(&response.post).await?;
+
+        Ok(ImageRequest::new(url, ImageRequestBody::SingleImage { image }))
+    }
+
+    async fn receive_pool(&mut self, base: &str, id: u64, url: String) -> FetchResult {
+        log::info!("Start e621 process pool {}", id);
+
+        let pool: Pool = self
+            .get(&format!("{}/pools/{}.json", base, id))
+            .await
+            .on_error(|_| log::error!("Error on request pool"))?;
+        if pool.post_ids.len() > MAX_POOL_POSTS {
+            log::warn!(
+                "Pool {} has {} posts, only the first {} are taken",
+                id,
+                pool.post_ids.len(),
+                MAX_POOL_POSTS
+            );
+        }
+
+        let response: PostsResponse = self
+            .get(&format!(
+                "{}/posts.json?tags=pool:{}&limit={}",
+                base, id, MAX_POOL_POSTS
+            ))
+            .await
+            .on_error(|_| log::error!("Error on request pool posts"))?;
+
+        // Search results are sorted by id, keep the pool order instead
+        let posts = pool
+            .post_ids
+            .iter()
+            .take(MAX_POOL_POSTS)
+            .filter_map(|id| response.posts.iter().find(|post| post.id == *id));
+        let images: Vec<_> = join_all(posts.map(|post| self.download(post)))
+            .await
+            .into_iter()
+            .filter_map(|r| r.on_error(|e| log::error!("{}", e)).ok())
+            .collect();
+
+        log::info!("Downloaded {} images", images.len());
+
+        let body = ImageRequestBody::from_images(images)
+            .ok_or_else(|| anyhow::anyhow!("Pool {} has no downloadable posts", id))?;
+        Ok(ImageRequest::new(url, body))
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for E621ReceiveActor {
+    fn name(&self) -> &str {
+        "e621"
+    }
+
+    fn link_patterns(&self) -> Vec<LinkPattern> {
+        vec![
+            LinkPattern::new(
+                r"https?://(?:www\.)?(?P<site>e621|e926)\.net/posts/(?P<id>\d+)",
+                "https://$site.net/posts/$id",
+            ),
+            LinkPattern::new(
+                r"https?://(?:www\.)?(?P<site>e621|e926)\.net/pools/(?P<id>\d+)",
+                "https://$site.net/pools/$id",
+            ),
+        ]
+    }
+
+    async fn fetch(&mut self, url: String) -> ActorResult<FetchResult> {
+        let link = url
+            .strip_prefix("https://")
+            .and_then(|path| {
+                let mut parts = path.split('/');
+                Some((parts.next()?, parts.next()?, parts.next()?.parse().ok()?))
+            })
+            .map(|(host, kind, id)| (format!("https://{}", host), kind.to_owned(), id));
+
+        Produces::ok(match link {
+            Some((base, kind, id)) if kind == "posts" => self.receive_post(&base, id, url).await,
+            Some((base, kind, id)) if kind == "pools" => self.receive_pool(&base, id, url).await,
+            _ => Err(anyhow::anyhow!("{} is not an e621 post or pool", url)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::source::canonical_links;
+
+    #[test]
+    fn recognizes_posts_and_pools_of_both_sites() {
+        let source = E621ReceiveActor::new(test_config(&[]));
+
+        assert_eq!(
+            canonical_links(
+                &source,
+                "https://www.e621.net/posts/123?q=fox https://e926.net/pools/45"
+            ),
+            vec!["https://e621.net/posts/123", "https://e926.net/pools/45"]
+        );
+        assert!(canonical_links(&source, "https://e621.net/posts?tags=fox").is_empty());
+    }
+}
@@ -0,0 +1,418 @@
+use crate::request::{Image, ImageRequest, ImageRequestBody};
+use crate::source::{FetchResult, LinkPattern, Source, Unsupported};
+use crate::utils::{
+    check_public_host, check_public_url, html_to_text, public_redirect_policy, ResultExtension,
+};
+use act_zero::{Actor, ActorResult, Produces};
+use futures::future::join_all;
+use reqwest::Url;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Software names of Misskey and its forks, everything else is expected to speak the Mastodon api.
+const MISSKEY_FAMILY: [&str; 8] = [
+    "misskey",
+    "sharkey",
+    "firefish",
+    "calckey",
+    "foundkey",
+    "iceshrimp",
+    "cherrypick",
+    "catodon",
+];
+
+#[derive(Deserialize)]
+struct NodeInfoLinks {
+    links: Vec<NodeInfoLink>,
+}
+
+#[derive(Deserialize)]
+struct NodeInfoLink {
+    rel: String,
+    href: String,
+}
+
+#[derive(Deserialize)]
+struct NodeInfo {
+    software: Software,
+}
+
+#[derive(Deserialize)]
+struct Software {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Status {
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    sensitive: bool,
+    #[serde(default)]
+    spoiler_text: String,
+    account: Account,
+    #[serde(default)]
+    media_attachments: Vec<MediaAttachment>,
+}
+
+#[derive(Deserialize)]
+struct Account {
+    acct: String,
+}
+
+#[derive(Deserialize)]
+struct MediaAttachment {
+    /// `image`, `gifv`, `video` or `audio`.
+    #[serde(rename = "type")]
+    kind: String,
+    url: Option<String>,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Note {
+    text: Option<String>,
+    cw: Option<String>,
+    user: User,
+    #[serde(default)]
+    files: Vec<DriveFile>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    username: String,
+    /// Missing for local users.
+    host: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DriveFile {
+    /// Mime type.
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    #[serde(default)]
+    is_sensitive: bool,
+    comment: Option<String>,
+}
+
+/// Media of a post before download.
+struct Attachment {
+    url: String,
+    video: bool,
+    alt: Option<String>,
+}
+
+/// Receives statuses from Mastodon, Pixelfed, Misskey and their forks on any instance.
+pub struct FediverseReceiveActor {
+    client: reqwest::Client,
+    /// Software name of every instance seen so far.
+    software: HashMap<String, String>,
+}
+
+impl Actor for FediverseReceiveActor {}
+
+impl FediverseReceiveActor {
+    pub fn new() -> Self {
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("heroku_bot/", env!("CARGO_PKG_VERSION")))
+            .redirect(public_redirect_policy())
+            .build()
+            .expect("Error on build client");
+
+        Self {
+            client,
+            software: HashMap::new(),
+        }
+    }
+
+    /// Asks the instance which software it runs through NodeInfo.
+    async fn software(&mut self, host: &str) -> anyhow::Result<String> {
+        if let Some(software) = self.software.get(host) {
+            return Ok(software.clone());
+        }
+
+        let links: NodeInfoLinks = self
+            .client
+            .get(format!("https://{}/.well-known/nodeinfo", host))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        // Prefer the newest schema
+        let link = links
+            .links
+            .iter()
+            .filter(|link| link.rel.starts_with("http://nodeinfo.diaspora.software/ns/schema/"))
+            .max_by(|a, b| a.rel.cmp(&b.rel))
+            .ok_or_else(|| anyhow::anyhow!("{} has no nodeinfo", host))?;
+
+        let node_info: NodeInfo = self
+            .client
+            .get(nodeinfo_url(host, &link.href)?)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let software = node_info.software.name.to_lowercase();
+        log::info!("{} runs {}", host, software);
+        self.software.insert(host.to_owned(), software.clone());
+        Ok(software)
+    }
+
+    async fn receive_status(&self, host: &str, id: &str, url: String) -> FetchResult {
+        let status: Status = self
+            .client
+            .get(format!("https://{}/api/v1/statuses/{}", host, id))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .on_error(|_| log::error!("Error on request status"))?;
+
+        let attachments = status
+            .media_attachments
+            .into_iter()
+            .filter(|media| media.kind != "audio")
+            .filter_map(|media| {
+                Some(Attachment {
+                    url: media.url?,
+                    video: media.kind == "gifv" || media.kind == "video",
+                    alt: media.description,
+                })
+            })
+            .collect();
+
+        let mut request = self.download_all(attachments, url).await?;
+        request.caption = Some(caption(
+            &status.spoiler_text,
+            &html_to_text(&status.content),
+            &status.account.acct,
+        ));
+        request.nsfw = status.sensitive || !status.spoiler_text.is_empty();
+        Ok(request)
+    }
+
+    async fn receive_note(&self, host: &str, id: &str, url: String) -> FetchResult {
+        let note: Note = self
+            .client
+            .post(format!("https://{}/api/notes/show", host))
+            .json(&serde_json::json!({ "noteId": id }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .on_error(|_| log::error!("Error on request note"))?;
+
+        let nsfw = note.cw.is_some() || note.files.iter().any(|file| file.is_sensitive);
+        let attachments = note
+            .files
+            .into_iter()
+            .filter(|file| file.kind.starts_with("image/") || file.kind.starts_with("video/"))
+            .map(|file| Attachment {
+                video: file.kind.starts_with("video/"),
+                alt: file.comment,
+                url: file.url,
+            })
+            .collect();
+
+        let acct = match &note.user.host {
+            Some(user_host) => format!("{}@{}", note.user.username, user_host),
+            None => note.user.username.clone(),
+        };
+
+        let mut request = self.download_all(attachments, url).await?;
+        request.caption = Some(caption(
+            note.cw.as_deref().unwrap_or_default(),
+            note.text.as_deref().unwrap_or_default(),
+            &acct,
+        ));
+        request.nsfw = nsfw;
+        Ok(request)
+    }
+
+    async fn download_all(&self, attachments: Vec<Attachment>, url: String) -> FetchResult {
+        let images: Vec<_> = join_all(attachments.iter().map(|media| self.download(media)))
+            .await
+            .into_iter()
+            .filter_map(|r| r.on_error(|e| log::error!("{}", e)).ok())
+            .collect();
+
+        log::info!("Downloaded {} images", images.len());
+
+        let body = ImageRequestBody::from_images(images)
+            .ok_or_else(|| anyhow::anyhow!("{} has no images", url))?;
+        Ok(ImageRequest::new(url, body))
+    }
+
+    async fn download(&self, media: &Attachment) -> anyhow::Result<Image> {
+        // Media urls come from the instance, which may point them anywhere
+        check_public_url(&media.url).await?;
+        let data = self
+            .client
+            .get(&media.url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+            .on_error(|_| log::error!("Error on download image"))?;
+
+        let path = media.url.split('?').next().unwrap();
+        let filename = path.rsplit('/').next().unwrap().to_string();
+        let mut image = if media.video {
+            Image::video(filename, data.as_ref().into())
+        } else {
+            Image::new(filename, data.as_ref().into())
+        };
+        image.alt = media.alt.clone().filter(|alt| !alt.trim().is_empty());
+        Ok(image)
+    }
+}
+
+/// Content warning, text and author of a post.
+fn caption(warning: &str, text: &str, acct: &str) -> String {
+    vec![
+        Some(format!("CW: {}", warning.trim())).filter(|_| !warning.trim().is_empty()),
+        Some(text.trim().to_owned()).filter(|text| !text.is_empty()),
+        Some(format!("@{}", acct)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join("\n\n")
+}
+
+/// NodeInfo document linked from the well-known endpoint, which has to stay on the instance.
+fn nodeinfo_url(host: &str, href: &str) -> anyhow::Result<Url> {
+    let url = Url::parse(href).map_err(|e| anyhow::anyhow!("Invalid nodeinfo link {}: {}", href, e))?;
+    let same_host = url
+        .host_str()
+        .is_some_and(|linked| linked.eq_ignore_ascii_case(host));
+    if url.scheme() != "https" || !same_host {
+        anyhow::bail!("Nodeinfo link {} of {} leaves the instance", href, host);
+    }
+    Ok(url)
+}
+
+#[async_trait::async_trait]
+impl Source for FediverseReceiveActor {
+    fn name(&self) -> &str {
+        "fediverse"
+    }
+
+    /// Recognizes Mastodon (`/@user/<id>`, `/users/<user>/statuses/<id>`), Pleroma (`/notice/<id>`),
+    /// Pixelfed (`/p/<user>/<id>`) and Misskey (`/notes/<id>`) post links on any host.
+    fn link_patterns(&self) -> Vec<LinkPattern> {
+        // The post id is always the last segment of the path
+        [
+            r"/@[\w.@-]+/\d+",
+            r"/users/[\w.-]+/statuses/\d+",
+            r"/notice/[0-9A-Za-z]+",
+            r"/p/[\w.-]+/\d+",
+            r"/notes/[0-9a-z]{10,}",
+        ]
+        .iter()
+        .map(|path| {
+            LinkPattern::new(
+                &format!(r"https?://(?P<host>[\w-]+(?:\.[\w-]+)+)(?P<path>{})", path),
+                "https://${host}${path}",
+            )
+        })
+        .collect()
+    }
+
+    async fn fetch(&mut self, url: String) -> ActorResult<FetchResult> {
+        let post = url.strip_prefix("https://").and_then(|path| {
+            let (host, path) = path.split_once('/')?;
+            let id = path.rsplit('/').next()?;
+            Some((host.to_owned(), id.to_owned()))
+        });
+        let (host, id) = match post {
+            Some(post) => post,
+            None => return Produces::ok(Err(anyhow::anyhow!("{} is not a fediverse post", url))),
+        };
+
+        // The patterns accept any host, internal ones included
+        if let Err(e) = check_public_host(&host).await {
+            return Produces::ok(Err(e));
+        }
+
+        log::info!("Start fediverse process {} on {}", id, host);
+        let software = match self.software(&host).await {
+            Ok(software) => software,
+            Err(e) => {
+                // The path only looks like a post, another source may know the site
+                let error = Unsupported(format!("{} is not a fediverse instance: {:#}", host, e));
+                return Produces::ok(Err(error.into()));
+            }
+        };
+
+        Produces::ok(if MISSKEY_FAMILY.contains(&software.as_str()) {
+            self.receive_note(&host, &id, url).await
+        } else {
+            self.receive_status(&host, &id, url).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::canonical_links;
+
+    #[test]
+    fn recognizes_statuses_and_notes() {
+        let source = FediverseReceiveActor::new();
+
+        assert_eq!(
+            canonical_links(
+                &source,
+                "https://mastodon.social/@artist/109876543210 \
+                 https://mastodon.social/@artist@example.com/109876543211 \
+                 https://example.com/users/artist/statuses/109876543212 \
+                 https://pleroma.example.com/notice/AbC123 \
+                 https://pixelfed.example.com/p/artist/123 \
+                 https://misskey.io/notes/9abcdefghi"
+            ),
+            vec![
+                "https://mastodon.social/@artist/109876543210",
+                "https://mastodon.social/@artist@example.com/109876543211",
+                "https://example.com/users/artist/statuses/109876543212",
+                "https://pleroma.example.com/notice/AbC123",
+                "https://pixelfed.example.com/p/artist/123",
+                "https://misskey.io/notes/9abcdefghi",
+            ]
+        );
+        assert!(canonical_links(&source, "https://mastodon.social/@artist").is_empty());
+    }
+
+    #[test]
+    fn follows_nodeinfo_links_on_the_instance_only() {
+        assert_eq!(
+            nodeinfo_url("mastodon.social", "https://mastodon.social/nodeinfo/2.0")
+                .unwrap()
+                .as_str(),
+            "https://mastodon.social/nodeinfo/2.0"
+        );
+        assert!(nodeinfo_url("Misskey.io", "https://misskey.io/nodeinfo/2.1").is_ok());
+
+        for href in [
+            "https://169.254.169.254/latest/meta-data",
+            "https://mastodon.social.example.com/nodeinfo/2.0",
+            "https://evil.example@mastodon.social.evil/nodeinfo",
+            "http://mastodon.social/nodeinfo/2.0",
+            "/nodeinfo/2.0",
+        ]
+        .iter()
+        {
+            assert!(nodeinfo_url("mastodon.social", href).is_err(), "{}", href);
+        }
+    }
+}
@@ -1,64 +1,237 @@
-use std::sync::Arc;
-use act_zero::{Actor, ActorResult, Addr, Produces};
-use crate::config::Config;
-use crate::processor::RequestProcessorActor;
+use act_zero::{Actor, ActorResult, Produces};
+use crate::config::GelbooruInstance;
 use crate::request::{Image, ImageRequest, ImageRequestBody};
+use crate::source::{host_pattern, FetchResult, LinkPattern, Source};
 use crate::utils::ResultExtension;
+use serde::Deserialize;
 
+#[derive(Deserialize)]
+struct Post {
+    id: u64,
+    /// Older versions of the engine only send `directory` and `image`.
+    file_url: Option<String>,
+    directory: Option<serde_json::Value>,
+    image: Option<String>,
+}
+
+/// Gelbooru wraps posts in an object, older versions of the engine return a bare list.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Posts {
+    Wrapped {
+        #[serde(default)]
+        post: Vec<Post>,
+    },
+    List(Vec<Post>),
+}
+
+/// Receives posts from a site running the Gelbooru engine.
 pub struct GelbooruReceiveActor {
-    client: gelbooru_api::Client,
-    processor: Addr<RequestProcessorActor>,
+    client: reqwest::Client,
+    instance: GelbooruInstance,
 }
 
 impl Actor for GelbooruReceiveActor {}
 
 impl GelbooruReceiveActor {
-    pub fn new(_config: Arc<Config>, processor: Addr<RequestProcessorActor>) -> Self {
-        let client = gelbooru_api::Client::public();
+    pub fn new(instance: GelbooruInstance) -> Self {
+        let client = reqwest::Client::new();
+        let instance = GelbooruInstance {
+            base_url: instance.base_url.trim_end_matches('/').to_owned(),
+            ..instance
+        };
 
-        Self { client, processor }
+        Self { client, instance }
     }
 
-    pub async fn receive_id(&mut self, id: u64, url: String) -> ActorResult<()> {
-        log::info!("Start gelbooru process {}", id);
+    async fn get_post(&self, id: u64) -> anyhow::Result<Post> {
+        let mut query = vec![
+            ("page", "dapi".to_owned()),
+            ("s", "post".to_owned()),
+            ("q", "index".to_owned()),
+            ("json", "1".to_owned()),
+            ("id", id.to_string()),
+        ];
+        if let Some(api_key) = &self.instance.api_key {
+            query.push(("api_key", api_key.clone()));
+        }
+        if let Some(user_id) = &self.instance.user_id {
+            query.push(("user_id", user_id.clone()));
+        }
+
+        let body = self
+            .client
+            .get(format!("{}/index.php", self.instance.base_url))
+            .query(&query)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        // Nothing at all is returned when the post does not exist
+        let posts = match body.trim() {
+            "" => Vec::new(),
+            body => match serde_json::from_str(body)? {
+                Posts::Wrapped { post } => post,
+                Posts::List(posts) => posts,
+            },
+        };
+
+        posts
+            .into_iter()
+            .find(|post| post.id == id)
+            .ok_or_else(|| anyhow::anyhow!("Post {} not found", id))
+    }
 
-        let result = gelbooru_api::posts()
-            .tag(format!("id:{}", id))
-            .limit(1)
-            .send(&self.client)
+    fn file_url(&self, post: &Post) -> Option<String> {
+        if let Some(url) = &post.file_url {
+            return Some(url.clone());
+        }
+
+        let directory = match post.directory.as_ref()? {
+            serde_json::Value::String(directory) => directory.clone(),
+            directory => directory.to_string(),
+        };
+        Some(format!(
+            "{}/images/{}/{}",
+            self.instance.base_url,
+            directory,
+            post.image.as_ref()?
+        ))
+    }
+
+    async fn receive_id(&mut self, id: u64, url: String) -> FetchResult {
+        log::info!("Start {} process {}", self.instance.name, id);
+
+        let post = self
+            .get_post(id)
             .await
-            .on_error(|x| log::error!("Error on request posts"))
-            .ok();
-
-        if let Some(result) = result {
-            if result.posts.len() >= 1 {
-                let post = result.posts.into_iter().next().unwrap();
-
-                let image = reqwest::get(&post.file_url)
-                    .await
-                    .on_error(|x| log::error!("Error on download image"))
-                    .ok();
-                let image = match image {
-                    None => None,
-                    Some(image) => image.bytes().await.ok()
-                };
-
-                if let Some(image) = image {
-                    let request = ImageRequest {
-                        source: url,
-                        body: ImageRequestBody::SingleImage {
-                            image: Image {
-                                filename: post.file_url.split_once("/").unwrap().1.to_string(),
-                                data: image.as_ref().to_owned().into()
-                            }
-                        }
-                    };
-
-                    act_zero::send!(self.processor.handle_request(request))
-                }
-            }
+            .on_error(|_| log::error!("Error on request posts"))?;
+        let file_url = self
+            .file_url(&post)
+            .ok_or_else(|| anyhow::anyhow!("Post {} has no file available", id))?;
+
+        let image = self
+            .client
+            .get(&file_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+            .on_error(|_| log::error!("Error on download image"))?;
+
+        Ok(ImageRequest::new(
+            url,
+            ImageRequestBody::SingleImage {
+                image: Image::new(
+                    file_url.rsplit('/').next().unwrap().to_string(),
+                    image.as_ref().to_owned().into(),
+                )
+            },
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for GelbooruReceiveActor {
+    fn name(&self) -> &str {
+        &self.instance.name
+    }
+
+    fn link_patterns(&self) -> Vec<LinkPattern> {
+        let host = self
+            .instance
+            .host
+            .clone()
+            .unwrap_or_else(|| host_pattern(&self.instance.base_url));
+
+        // Pools, wiki and forum pages have ids too, only posts are fetched
+        let canonical = format!("{}/index.php?page=post&s=view&id=$id", self.instance.base_url);
+        let link = format!(r"https?://(?:www\.)?(?:{})/index\.php\?(?:\S*?&)?", host);
+        vec![
+            LinkPattern::new(
+                &format!(r"{}page=post&(?:\S*?&)?id=(?P<id>\d+)\S*", link),
+                &canonical,
+            ),
+            LinkPattern::new(
+                &format!(r"{}id=(?P<id>\d+)&(?:\S*?&)?page=post\b\S*", link),
+                &canonical,
+            ),
+        ]
+    }
+
+    async fn fetch(&mut self, url: String) -> ActorResult<FetchResult> {
+        let id = url
+            .rsplit_once("id=")
+            .and_then(|(_, id)| id.parse().ok());
+
+        Produces::ok(match id {
+            Some(id) => self.receive_id(id, url).await,
+            None => Err(anyhow::anyhow!("{} is not a {} post", url, self.instance.name)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::source::canonical_links;
+
+    #[test]
+    fn recognizes_post_links() {
+        let instance = test_config(&[]).gelbooru_instances[0].clone();
+        let source = GelbooruReceiveActor::new(instance);
+
+        assert_eq!(
+            canonical_links(
+                &source,
+                "https://gelbooru.com/index.php?page=post&s=view&id=123&tags=sky \
+                 https://www.gelbooru.com/index.php?id=456&page=post&s=view"
+            ),
+            vec![
+                "https://gelbooru.com/index.php?page=post&s=view&id=123",
+                "https://gelbooru.com/index.php?page=post&s=view&id=456",
+            ]
+        );
+        assert!(canonical_links(&source, "https://gelbooru.com/index.php?page=post&s=list").is_empty());
+    }
+
+    #[test]
+    fn ignores_pages_other_than_posts() {
+        let instance = test_config(&[]).gelbooru_instances[0].clone();
+        let source = GelbooruReceiveActor::new(instance);
+
+        for link in [
+            "https://gelbooru.com/index.php?page=pool&s=show&id=123",
+            "https://gelbooru.com/index.php?page=wiki&s=view&id=123",
+            "https://gelbooru.com/index.php?page=forum&s=view&id=123",
+            "https://gelbooru.com/index.php?id=123&page=forum&s=view",
+            "https://gelbooru.com/index.php?page=posts&s=view&id=123",
+            "https://gelbooru.com/index.php?page=post&s=view&pool_id=123",
+        ]
+        .iter()
+        {
+            assert!(canonical_links(&source, link).is_empty(), "{}", link);
         }
+    }
+
+    #[test]
+    fn uses_the_configured_host() {
+        let source = GelbooruReceiveActor::new(GelbooruInstance {
+            name: "safebooru".to_owned(),
+            base_url: "https://safebooru.org/".to_owned(),
+            host: Some(r"(?:www\.)?safebooru\.org".to_owned()),
+            api_key: None,
+            user_id: None,
+        });
 
-        Produces::ok(())
+        assert_eq!(
+            canonical_links(&source, "https://safebooru.org/index.php?page=post&s=view&id=7"),
+            vec!["https://safebooru.org/index.php?page=post&s=view&id=7"]
+        );
+        assert!(canonical_links(&source, "https://gelbooru.com/index.php?page=post&s=view&id=7").is_empty());
     }
 }
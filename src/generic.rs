@@ -0,0 +1,377 @@
+use crate::config::Config;
+use crate::request::{Image, ImageRequest, ImageRequestBody};
+use crate::source::{FetchResult, LinkPattern, Source};
+use crate::utils::{check_public_url, decode_entities, public_redirect_policy, ResultExtension};
+use act_zero::{Actor, ActorResult, Produces};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Page metadata naming the main picture, most preferred first.
+const IMAGE_PROPERTIES: [&str; 5] = [
+    "og:image",
+    "og:image:url",
+    "og:image:secure_url",
+    "twitter:image",
+    "twitter:image:src",
+];
+
+static META: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?is)<meta\s[^>]*>").unwrap());
+static LINK: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?is)<link\s[^>]*>").unwrap());
+static ATTRIBUTE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?s)([\w:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap()
+});
+
+/// A response body read up to the size limit.
+struct Fetched {
+    url: Url,
+    /// Mime type without parameters.
+    content_type: Option<String>,
+    data: Vec<u8>,
+}
+
+/// Receives any other link: images as they are, pages through the picture of their link preview.
+pub struct GenericReceiveActor {
+    client: reqwest::Client,
+    allowed_domains: Vec<String>,
+    denied_domains: Vec<String>,
+    max_size: u64,
+}
+
+impl Actor for GenericReceiveActor {}
+
+impl GenericReceiveActor {
+    pub fn new(config: Arc<Config>) -> Self {
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("heroku_bot/", env!("CARGO_PKG_VERSION")))
+            .redirect(public_redirect_policy())
+            .build()
+            .expect("Error on build client");
+        let normalize = |domains: &[String]| {
+            domains
+                .iter()
+                .map(|domain| domain.trim().trim_start_matches('.').to_lowercase())
+                .filter(|domain| !domain.is_empty())
+                .collect()
+        };
+
+        Self {
+            client,
+            allowed_domains: normalize(&config.generic_allowed_domains),
+            denied_domains: normalize(&config.generic_denied_domains),
+            max_size: config.generic_max_size,
+        }
+    }
+
+    /// Fails unless `url` is on the allowed domains and on a public host.
+    async fn check_link(&self, url: &str) -> anyhow::Result<()> {
+        if !is_domain_allowed(url, &self.allowed_domains, &self.denied_domains) {
+            anyhow::bail!("{} is not on the allowed domains", url);
+        }
+        check_public_url(url).await
+    }
+
+    /// Downloads `url` and fails as soon as it turns out larger than the limit.
+    ///
+    /// Both the link and where it redirects to have to pass [`Self::check_link`].
+    async fn download(&self, url: &str) -> anyhow::Result<Fetched> {
+        self.check_link(url).await?;
+        let mut response = self.client.get(url).send().await?.error_for_status()?;
+        if response.url().as_str() != url {
+            self.check_link(response.url().as_str()).await?;
+        }
+        if response.content_length().is_some_and(|length| length > self.max_size) {
+            anyhow::bail!("{} is larger than {} bytes", url, self.max_size);
+        }
+
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|value| value.trim().to_lowercase());
+        let final_url = response.url().clone();
+
+        // The length header may be missing or lie
+        let mut data = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            data.extend_from_slice(&chunk);
+            if data.len() as u64 > self.max_size {
+                anyhow::bail!("{} is larger than {} bytes", url, self.max_size);
+            }
+        }
+
+        Ok(Fetched {
+            url: final_url,
+            content_type,
+            data,
+        })
+    }
+
+    async fn receive(&mut self, url: String) -> FetchResult {
+        log::info!("Start generic process {}", url);
+
+        let fetched = self
+            .download(&url)
+            .await
+            .on_error(|_| log::error!("Error on request {}", url))?;
+
+        let mut caption = None;
+        let image = if let Some(extension) = sniff_image(&fetched.data) {
+            to_image(fetched, extension)
+        } else if is_html(fetched.content_type.as_deref()) {
+            let page = String::from_utf8_lossy(&fetched.data);
+            let image_url = find_image(&page, &fetched.url)
+                .ok_or_else(|| anyhow::anyhow!("{} has no preview image", url))?;
+            caption = find_title(&page);
+
+            let image = self
+                .download(image_url.as_str())
+                .await
+                .on_error(|_| log::error!("Error on download image"))?;
+            let extension = sniff_image(&image.data).ok_or_else(|| {
+                anyhow::anyhow!("Preview {} of {} is not an image", image_url, url)
+            })?;
+            to_image(image, extension)
+        } else {
+            match fetched.content_type.as_deref() {
+                Some(image) if image.starts_with("image/") => {
+                    anyhow::bail!("{} is sent as {} but is not a supported image", url, image)
+                }
+                content_type => anyhow::bail!(
+                    "{} is neither an image nor a page, but {}",
+                    url,
+                    content_type.unwrap_or("unknown")
+                ),
+            }
+        };
+
+        let mut request = ImageRequest::new(url, ImageRequestBody::SingleImage { image });
+        request.caption = caption;
+        Ok(request)
+    }
+}
+
+/// Extension of the image format the data starts with, regardless of what the server claims.
+fn sniff_image(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("jpg")
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("gif")
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some("webp")
+    } else {
+        None
+    }
+}
+
+fn is_html(content_type: Option<&str>) -> bool {
+    matches!(content_type, Some("text/html") | Some("application/xhtml+xml"))
+}
+
+fn to_image(fetched: Fetched, extension: &str) -> Image {
+    let name = fetched
+        .url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| name.contains('.'))
+        .map(str::to_owned)
+        .unwrap_or_else(|| format!("image.{}", extension));
+    if extension == "gif" {
+        Image::animation(name, fetched.data.into(), None)
+    } else {
+        Image::new(name, fetched.data.into())
+    }
+}
+
+/// Attributes of every element on the page matched by `element`, with lowercase names.
+fn elements(page: &str, element: &Regex) -> Vec<HashMap<String, String>> {
+    element
+        .find_iter(page)
+        .map(|element| {
+            ATTRIBUTE
+                .captures_iter(element.as_str())
+                .map(|captures| {
+                    let value = captures
+                        .get(2)
+                        .or_else(|| captures.get(3))
+                        .or_else(|| captures.get(4))
+                        .map_or("", |value| value.as_str());
+                    (captures[1].to_lowercase(), decode_entities(value))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Main picture of the page from its OpenGraph or Twitter card metadata, or its `image_src` link.
+fn find_image(page: &str, base: &Url) -> Option<Url> {
+    let metas = elements(page, &META);
+    let meta = IMAGE_PROPERTIES.iter().find_map(|property| {
+        metas.iter().find_map(|meta| {
+            let key = meta.get("property").or_else(|| meta.get("name"))?;
+            Some(meta.get("content")?.as_str())
+                .filter(|_| key.eq_ignore_ascii_case(property))
+                .filter(|content| !content.is_empty())
+        })
+    });
+
+    let links = elements(page, &LINK);
+    let link = || {
+        links.iter().find_map(|link| {
+            let rel = link.get("rel")?;
+            Some(link.get("href")?.as_str()).filter(|_| {
+                rel.split_whitespace()
+                    .any(|rel| rel.eq_ignore_ascii_case("image_src"))
+            })
+        })
+    };
+
+    base.join(meta.or_else(link)?).ok()
+}
+
+fn find_title(page: &str) -> Option<String> {
+    elements(page, &META).into_iter().find_map(|mut meta| {
+        let key = meta.get("property").or_else(|| meta.get("name"))?;
+        if !key.eq_ignore_ascii_case("og:title") {
+            return None;
+        }
+        meta.remove("content").filter(|title| !title.is_empty())
+    })
+}
+
+fn is_domain_allowed(url: &str, allowed: &[String], denied: &[String]) -> bool {
+    let host = match Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_lowercase)) {
+        Some(host) => host,
+        None => return false,
+    };
+    (allowed.is_empty() || allowed.iter().any(|domain| matches_domain(&host, domain)))
+        && !denied.iter().any(|domain| matches_domain(&host, domain))
+}
+
+fn matches_domain(host: &str, domain: &str) -> bool {
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+#[async_trait::async_trait]
+impl Source for GenericReceiveActor {
+    fn name(&self) -> &str {
+        "generic"
+    }
+
+    /// Recognizes any link on the allowed and not denied domains.
+    fn link_patterns(&self) -> Vec<LinkPattern> {
+        let allowed = self.allowed_domains.clone();
+        let denied = self.denied_domains.clone();
+
+        // Punctuation right after a link usually belongs to the sentence
+        vec![
+            LinkPattern::new(r#"https?://[^\s<>"']*[^\s<>"'.,;:!?)\]]"#, "$0")
+                .with_filter(move |url| is_domain_allowed(url, &allowed, &denied)),
+        ]
+    }
+
+    async fn fetch(&mut self, url: String) -> ActorResult<FetchResult> {
+        Produces::ok(self.receive(url).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::source::canonical_links;
+
+    #[test]
+    fn sniffs_images_by_their_signature() {
+        assert_eq!(sniff_image(&[0xFF, 0xD8, 0xFF, 0xE0, 0, 0]), Some("jpg"));
+        assert_eq!(sniff_image(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some("png"));
+        assert_eq!(sniff_image(b"GIF89a\x01\0\x01\0"), Some("gif"));
+        assert_eq!(sniff_image(b"GIF87a\x01\0\x01\0"), Some("gif"));
+        assert_eq!(sniff_image(b"RIFF\x24\0\0\0WEBPVP8 "), Some("webp"));
+        assert_eq!(sniff_image(b"RIFF\x24\0\0\0WAVEfmt "), None);
+        assert_eq!(sniff_image(b"RIFF"), None);
+        assert_eq!(sniff_image(b"<!DOCTYPE html>"), None);
+        assert_eq!(sniff_image(b""), None);
+    }
+
+    #[test]
+    fn finds_the_preview_image_and_title() {
+        let page = r#"<html><head>
+            <meta name="twitter:image" content="https://cdn.example.com/card.png">
+            <meta content="/images/og.jpg?w=1200&amp;h=630" property="og:image" />
+            <meta property='og:title' content='Tom &amp; Jerry'>
+            <link rel="image_src" href="/images/link.jpg">
+        </head></html>"#;
+        let base = Url::parse("https://example.com/posts/1").unwrap();
+
+        assert_eq!(
+            find_image(page, &base).unwrap().as_str(),
+            "https://example.com/images/og.jpg?w=1200&h=630"
+        );
+        assert_eq!(find_title(page), Some("Tom & Jerry".to_owned()));
+
+        let linked = r#"<link rel="icon image_src" href="/images/link.jpg">"#;
+        assert_eq!(
+            find_image(linked, &base).unwrap().as_str(),
+            "https://example.com/images/link.jpg"
+        );
+        assert_eq!(find_image("<p>No metadata</p>", &base), None);
+    }
+
+    #[test]
+    fn recognizes_links_on_allowed_domains_only() {
+        let source = GenericReceiveActor::new(test_config(&[
+            ("GENERIC_ALLOWED_DOMAINS", "example.com,.Example.org"),
+            ("GENERIC_DENIED_DOMAINS", "private.example.com"),
+        ]));
+
+        assert_eq!(
+            canonical_links(
+                &source,
+                "(https://example.com/a.png), https://img.example.org/b.jpg. \
+                 https://private.example.com/c.png https://notexample.com/d.png"
+            ),
+            vec!["https://example.com/a.png", "https://img.example.org/b.jpg"]
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_to_download_from_denied_or_internal_hosts() {
+        let source = GenericReceiveActor::new(test_config(&[(
+            "GENERIC_DENIED_DOMAINS",
+            "private.example.com",
+        )]));
+
+        for url in [
+            "https://private.example.com/a.png",
+            "http://127.0.0.1/b.png",
+            "http://[::1]/c.png",
+            "http://localhost:8080/d.png",
+        ] {
+            assert!(source.download(url).await.is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn sends_gifs_as_animations() {
+        let fetched = |path: &str, data: &[u8]| Fetched {
+            url: Url::parse("https://example.com").unwrap().join(path).unwrap(),
+            content_type: None,
+            data: data.to_vec(),
+        };
+
+        let gif = to_image(fetched("/media/cat.gif", b"GIF89a\x01\0\x01\0"), "gif");
+        assert!(gif.is_animation());
+        assert_eq!(gif.filename, "cat.gif");
+
+        let png = to_image(fetched("/media/", b"\x89PNG\r\n\x1a\n"), "png");
+        assert!(!png.is_animation());
+        assert_eq!(png.filename, "image.png");
+    }
+}
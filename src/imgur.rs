@@ -0,0 +1,321 @@
+use crate::config::Config;
+use crate::request::{Image, ImageRequest, ImageRequestBody};
+use crate::source::{FetchResult, LinkPattern, Source};
+use crate::utils::ResultExtension;
+use act_zero::{Actor, ActorResult, Produces};
+use futures::future::join_all;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// Pages of the site whose path looks like an image id.
+const SITE_PAGES: [&str; 16] = [
+    "about", "account", "emerald", "login", "logout", "memegen", "messages", "privacy",
+    "random", "register", "search", "settings", "signin", "signup", "upgrade", "upload",
+];
+
+#[derive(Deserialize)]
+struct Response<T> {
+    data: T,
+}
+
+#[derive(Deserialize)]
+struct Album {
+    title: Option<String>,
+    nsfw: Option<bool>,
+    #[serde(default)]
+    images: Vec<ImgurImage>,
+}
+
+#[derive(Deserialize)]
+struct ImgurImage {
+    title: Option<String>,
+    description: Option<String>,
+    nsfw: Option<bool>,
+    /// The original file, a gif for animations.
+    link: String,
+    #[serde(default)]
+    animated: bool,
+    /// Present for animations, the way Imgur serves them as `.gifv`.
+    mp4: Option<String>,
+}
+
+/// Receives images and albums from Imgur.
+pub struct ImgurReceiveActor {
+    client: reqwest::Client,
+    api_url: String,
+    client_id: String,
+}
+
+impl Actor for ImgurReceiveActor {}
+
+impl ImgurReceiveActor {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_url: config.imgur_api_url.trim_end_matches('/').to_owned(),
+            client_id: config
+                .imgur_client_id
+                .clone()
+                .expect("Imgur client id is required"),
+        }
+    }
+
+    async fn api<T: DeserializeOwned>(&self, path: &str) -> anyhow::Result<T> {
+        let response: Response<T> = self
+            .client
+            .get(format!("{}/3/{}", self.api_url, path))
+            .header("Authorization", format!("Client-ID {}", self.client_id))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.data)
+    }
+
+    /// Animations are downloaded as mp4 instead of the far larger gif.
+    async fn download(&self, image: &ImgurImage) -> anyhow::Result<Image> {
+        let url = media_url(image);
+        let data = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+            .on_error(|_| log::error!("Error on download image"))?;
+
+        Ok(to_image(image, url, data.as_ref().into()))
+    }
+
+    async fn receive_album(&mut self, id: &str, url: String) -> FetchResult {
+        log::info!("Start imgur process album {}", id);
+
+        let album: Album = self
+            .api(&format!("album/{}", id))
+            .await
+            .on_error(|_| log::error!("Error on request album"))?;
+        self.album_request(id, album, url).await
+    }
+
+    async fn receive_image(&mut self, id: &str, url: String) -> FetchResult {
+        log::info!("Start imgur process image {}", id);
+
+        let image: ImgurImage = self
+            .api(&format!("image/{}", id))
+            .await
+            .on_error(|_| log::error!("Error on request image"))?;
+        self.image_request(image, url).await
+    }
+
+    /// Gallery posts are albums or single images, told apart by `is_album`.
+    async fn receive_gallery(&mut self, id: &str, url: String) -> FetchResult {
+        log::info!("Start imgur process gallery {}", id);
+
+        let post: Value = self
+            .api(&format!("gallery/{}", id))
+            .await
+            .on_error(|_| log::error!("Error on request gallery"))?;
+        if post["is_album"].as_bool().unwrap_or_default() {
+            self.album_request(id, serde_json::from_value(post)?, url).await
+        } else {
+            self.image_request(serde_json::from_value(post)?, url).await
+        }
+    }
+
+    async fn album_request(&self, id: &str, album: Album, url: String) -> FetchResult {
+        let images: Vec<_> = join_all(album.images.iter().map(|image| self.download(image)))
+            .await
+            .into_iter()
+            .filter_map(|r| r.on_error(|e| log::error!("{}", e)).ok())
+            .collect();
+
+        log::info!("Downloaded {} images", images.len());
+
+        let body = ImageRequestBody::from_images(images)
+            .ok_or_else(|| anyhow::anyhow!("Album {} has no images", id))?;
+        let mut request = ImageRequest::new(url, body);
+        request.caption = album.title.filter(|title| !title.trim().is_empty());
+        request.nsfw = album.nsfw.unwrap_or_default();
+        Ok(request)
+    }
+
+    async fn image_request(&self, image: ImgurImage, url: String) -> FetchResult {
+        let downloaded = self.download(&image).await?;
+        let mut request = ImageRequest::new(url, ImageRequestBody::SingleImage { image: downloaded });
+        request.caption = image.title.filter(|title| !title.trim().is_empty());
+        request.nsfw = image.nsfw.unwrap_or_default();
+        Ok(request)
+    }
+}
+
+/// The MP4 of animations when Imgur has one, the original file otherwise.
+fn media_url(image: &ImgurImage) -> &str {
+    match (&image.mp4, image.animated) {
+        (Some(mp4), true) => mp4,
+        _ => &image.link,
+    }
+}
+
+/// Animations, served as MP4 (`.gifv`) or only as a GIF, are sent as animations.
+fn to_image(image: &ImgurImage, url: &str, data: Arc<[u8]>) -> Image {
+    let path = url.split('?').next().unwrap();
+    let filename = path.rsplit('/').next().unwrap().to_string();
+
+    let mut converted = if !image.animated {
+        Image::new(filename, data)
+    } else if image.mp4.as_deref() == Some(url) {
+        Image::animation(filename, data.clone(), Some(data))
+    } else {
+        Image::animation(filename, data, None)
+    };
+    converted.alt = image.description.clone().filter(|alt| !alt.trim().is_empty());
+    converted
+}
+
+#[async_trait::async_trait]
+impl Source for ImgurReceiveActor {
+    fn name(&self) -> &str {
+        "imgur"
+    }
+
+    /// Recognizes album and gallery links, whose path may carry a slug before the id,
+    /// and single images on the page or direct links.
+    fn link_patterns(&self) -> Vec<LinkPattern> {
+        vec![
+            LinkPattern::new(
+                r"https?://(?:www\.|m\.)?imgur\.com/a/(?:[\w-]*-)?(?P<id>[A-Za-z0-9]{5,8})\b",
+                "https://imgur.com/a/$id",
+            ),
+            LinkPattern::new(
+                r"https?://(?:www\.|m\.)?imgur\.com/gallery/(?:[\w-]*-)?(?P<id>[A-Za-z0-9]{5,8})\b",
+                "https://imgur.com/gallery/$id",
+            ),
+            // The id has to be the whole path, pages of the site look the same otherwise
+            LinkPattern::new(
+                r"https?://(?:www\.|m\.|i\.)?imgur\.com/(?P<id>[A-Za-z0-9]{5,8})(?:$|[^\w/])",
+                "https://imgur.com/$id",
+            )
+            .with_filter(|url| {
+                let id = url.trim_start_matches("https://imgur.com/");
+                !SITE_PAGES.iter().any(|page| page.eq_ignore_ascii_case(id))
+            }),
+        ]
+    }
+
+    async fn fetch(&mut self, url: String) -> ActorResult<FetchResult> {
+        let path = url.strip_prefix("https://imgur.com/").map(str::to_owned);
+
+        Produces::ok(match path {
+            Some(path) => {
+                if let Some(id) = path.strip_prefix("a/") {
+                    self.receive_album(id, url).await
+                } else if let Some(id) = path.strip_prefix("gallery/") {
+                    self.receive_gallery(id, url).await
+                } else {
+                    self.receive_image(&path, url).await
+                }
+            }
+            None => Err(anyhow::anyhow!("{} is not an imgur link", url)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::source::canonical_links;
+
+    fn source() -> ImgurReceiveActor {
+        ImgurReceiveActor::new(test_config(&[("IMGUR_CLIENT_ID", "id")]))
+    }
+
+    #[test]
+    fn recognizes_albums_and_galleries() {
+        assert_eq!(
+            canonical_links(
+                &source(),
+                "https://imgur.com/a/AbC12 https://m.imgur.com/a/some-title-DeF3456 \
+                 https://imgur.com/gallery/GhI7890 https://www.imgur.com/gallery/a-cat-JkL1234"
+            ),
+            vec![
+                "https://imgur.com/a/AbC12",
+                "https://imgur.com/a/DeF3456",
+                "https://imgur.com/gallery/GhI7890",
+                "https://imgur.com/gallery/JkL1234",
+            ]
+        );
+    }
+
+    #[test]
+    fn recognizes_single_images() {
+        assert_eq!(
+            canonical_links(
+                &source(),
+                "https://imgur.com/AbC1234 (https://i.imgur.com/DeF5678.jpg) https://imgur.com/GhI9012?r"
+            ),
+            vec![
+                "https://imgur.com/AbC1234",
+                "https://imgur.com/DeF5678",
+                "https://imgur.com/GhI9012",
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_pages_of_the_site() {
+        for link in [
+            "https://imgur.com/upload",
+            "https://imgur.com/signin",
+            "https://imgur.com/about",
+            "https://imgur.com/Upload?redirect=x",
+            "https://imgur.com/user/AbC1234",
+            "https://imgur.com/AbC1234/comment/5",
+            "https://imgur.com/t/cats",
+        ]
+        .iter()
+        {
+            assert!(canonical_links(&source(), link).is_empty(), "{}", link);
+        }
+    }
+
+    fn item(animated: bool, mp4: Option<&str>) -> ImgurImage {
+        ImgurImage {
+            title: None,
+            description: Some("A cat".to_owned()),
+            nsfw: None,
+            link: format!("https://i.imgur.com/AbC1234.{}", if animated { "gif" } else { "png" }),
+            animated,
+            mp4: mp4.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn sends_animations_as_animations() {
+        let data: Arc<[u8]> = Arc::from(&b"data"[..]);
+
+        let still = item(false, None);
+        let image = to_image(&still, media_url(&still), data.clone());
+        assert!(!image.is_animation() && !image.is_video());
+        assert_eq!(image.filename, "AbC1234.png");
+        assert_eq!(image.alt.as_deref(), Some("A cat"));
+
+        let gifv = item(true, Some("https://i.imgur.com/AbC1234.mp4"));
+        assert_eq!(media_url(&gifv), "https://i.imgur.com/AbC1234.mp4");
+        let image = to_image(&gifv, media_url(&gifv), data.clone());
+        assert!(image.is_animation());
+        assert_eq!(image.filename, "AbC1234.mp4");
+        assert!(image.mp4.is_some());
+
+        let gif = item(true, None);
+        assert_eq!(media_url(&gif), "https://i.imgur.com/AbC1234.gif");
+        let image = to_image(&gif, media_url(&gif), data);
+        assert!(image.is_animation());
+        assert_eq!(image.filename, "AbC1234.gif");
+        assert!(image.mp4.is_none());
+    }
+}
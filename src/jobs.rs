@@ -0,0 +1,625 @@
+use crate::processor::{Delivery, RequestProcessorActor};
+use crate::request::ImageRequest;
+use crate::source::{Link, SourceRegistry};
+use crate::utils::ResultExtension;
+use act_zero::{call, send, Actor, ActorResult, Addr, AddrLike, Produces, WeakAddr};
+use chrono::{DateTime, Utc};
+use futures::channel::oneshot;
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::fmt;
+use std::future::Future;
+
+pub type JobId = u64;
+
+#[derive(Clone)]
+pub enum JobInput {
+    Link(Link),
+    /// Request built from uploaded files, there is nothing to fetch.
+    Upload(ImageRequest),
+}
+
+impl JobInput {
+    pub fn source(&self) -> &str {
+        match self {
+            JobInput::Link(link) => &link.source,
+            JobInput::Upload(_) => "upload",
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        match self {
+            JobInput::Link(link) => &link.url,
+            JobInput::Upload(request) => &request.source,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct JobSpec {
+    pub input: JobInput,
+    /// Names of the targets to deliver to, `None` means every target.
+    pub targets: Option<Vec<String>>,
+    /// Replaces the caption provided by the source.
+    pub caption: Option<String>,
+    /// Added to the tags provided by the source.
+    pub tags: Vec<String>,
+    /// Jobs with higher priority are started first.
+    pub priority: i32,
+}
+
+impl JobSpec {
+    pub fn new(input: JobInput) -> Self {
+        Self {
+            input,
+            targets: None,
+            caption: None,
+            tags: Vec::new(),
+            priority: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Fetching,
+    Fetched,
+    Delivering,
+    Delivered,
+    PartiallyDelivered,
+    Failed,
+}
+
+impl JobState {
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            JobState::Delivered | JobState::PartiallyDelivered | JobState::Failed
+        )
+    }
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            JobState::Queued => "queued",
+            JobState::Fetching => "fetching",
+            JobState::Fetched => "fetched",
+            JobState::Delivering => "delivering",
+            JobState::Delivered => "delivered",
+            JobState::PartiallyDelivered => "partially delivered",
+            JobState::Failed => "failed",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum TargetState {
+    Delivering,
+    Delivered,
+    Failed { error: String },
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Job {
+    pub id: JobId,
+    pub source: String,
+    pub url: String,
+    pub priority: i32,
+    pub state: JobState,
+    /// Why the job failed before reaching the targets.
+    pub error: Option<String>,
+    pub targets: BTreeMap<String, TargetState>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Job {
+    /// Human readable state of the job and of every target, for chat replies.
+    pub fn summary(&self) -> String {
+        let mut summary = format!("Job {} ({}) {}", self.id, self.source, self.state);
+        if let Some(error) = &self.error {
+            summary.push_str(&format!(": {}", error));
+        }
+
+        for (target, state) in &self.targets {
+            let state = match state {
+                TargetState::Delivering => "delivering".to_owned(),
+                TargetState::Delivered => "delivered".to_owned(),
+                TargetState::Failed { error } => format!("failed: {}", error),
+            };
+            summary.push_str(&format!("\n{}: {}", target, state));
+        }
+
+        summary
+    }
+
+    fn set_state(&mut self, state: JobState) {
+        self.state = state;
+        self.updated_at = Utc::now();
+    }
+
+    fn update_target(&mut self, target: String, state: TargetState) {
+        self.targets.insert(target, state);
+        self.updated_at = Utc::now();
+
+        let pending = self
+            .targets
+            .values()
+            .any(|state| matches!(state, TargetState::Delivering));
+        if pending {
+            return;
+        }
+
+        let failed = self
+            .targets
+            .values()
+            .filter(|state| matches!(state, TargetState::Failed { .. }))
+            .count();
+        self.state = if failed == 0 {
+            JobState::Delivered
+        } else if failed == self.targets.len() {
+            JobState::Failed
+        } else {
+            JobState::PartiallyDelivered
+        };
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct JobStats {
+    pub queued: usize,
+    pub running: usize,
+    /// Number of stored jobs in every state.
+    pub states: BTreeMap<JobState, usize>,
+}
+
+#[derive(Deserialize)]
+pub struct JobFilter {
+    pub state: Option<JobState>,
+    pub source: Option<String>,
+    /// Only jobs delivered, or being delivered, to this target.
+    pub target: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl JobFilter {
+    fn matches(&self, job: &Job) -> bool {
+        self.state.iter().all(|state| job.state == *state)
+            && self.source.iter().all(|source| &job.source == source)
+            && self.target.iter().all(|target| job.targets.contains_key(target))
+    }
+}
+
+struct QueuedJob {
+    id: JobId,
+    spec: JobSpec,
+}
+
+impl Ord for QueuedJob {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.spec
+            .priority
+            .cmp(&other.spec.priority)
+            .then_with(|| other.id.cmp(&self.id))
+    }
+}
+
+impl PartialOrd for QueuedJob {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for QueuedJob {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for QueuedJob {}
+
+enum JobUpdate {
+    State(JobState),
+    Failed(String),
+    Target(String, TargetState),
+}
+
+/// Queues submitted jobs, runs at most `max_running` of them at once and keeps
+/// the state of the last `history` jobs.
+pub struct JobManagerActor {
+    addr: WeakAddr<Self>,
+    processor: Addr<RequestProcessorActor>,
+    next_id: JobId,
+    queue: BinaryHeap<QueuedJob>,
+    running: usize,
+    max_running: usize,
+    jobs: BTreeMap<JobId, Job>,
+    history: usize,
+    /// Specs of the stored link jobs, to submit them again. Uploads are not kept,
+    /// their images would stay in memory for as long as the job is stored.
+    specs: HashMap<JobId, JobSpec>,
+    waiters: HashMap<JobId, Vec<oneshot::Sender<Produces<Option<Job>>>>>,
+}
+
+#[async_trait::async_trait]
+impl Actor for JobManagerActor {
+    async fn started(&mut self, addr: Addr<Self>) -> ActorResult<()>
+    where
+        Self: Sized,
+    {
+        self.addr = addr.downgrade();
+        Produces::ok(())
+    }
+}
+
+impl JobManagerActor {
+    pub fn new(processor: Addr<RequestProcessorActor>, max_running: usize, history: usize) -> Self {
+        Self {
+            addr: WeakAddr::detached(),
+            processor,
+            next_id: 1,
+            queue: BinaryHeap::new(),
+            running: 0,
+            max_running: max_running.max(1),
+            jobs: BTreeMap::new(),
+            history,
+            specs: HashMap::new(),
+            waiters: HashMap::new(),
+        }
+    }
+
+    pub async fn submit(&mut self, spec: JobSpec) -> ActorResult<JobId> {
+        Produces::ok(self.enqueue(spec))
+    }
+
+    fn enqueue(&mut self, spec: JobSpec) -> JobId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        log::info!("Queue job {} for {}", id, spec.input.url());
+        let now = Utc::now();
+        self.jobs.insert(
+            id,
+            Job {
+                id,
+                source: spec.input.source().to_owned(),
+                url: spec.input.url().to_owned(),
+                priority: spec.priority,
+                state: JobState::Queued,
+                error: None,
+                targets: BTreeMap::new(),
+                created_at: now,
+                updated_at: now,
+            },
+        );
+        if let JobInput::Link(_) = spec.input {
+            self.specs.insert(id, spec.clone());
+        }
+        self.forget_old_jobs();
+
+        self.queue.push(QueuedJob { id, spec });
+        self.start_jobs();
+
+        id
+    }
+
+    /// Submits a finished link job again as a new job.
+    pub async fn retry(&mut self, id: JobId) -> ActorResult<anyhow::Result<JobId>> {
+        let spec = match (self.jobs.get(&id), self.specs.get(&id)) {
+            (None, _) => return Produces::ok(Err(anyhow::anyhow!("Job {} not found", id))),
+            (Some(job), _) if !job.state.is_finished() => {
+                return Produces::ok(Err(anyhow::anyhow!("Job {} is still {}", id, job.state)))
+            }
+            (Some(_), Some(spec)) => spec.clone(),
+            (Some(_), None) => {
+                return Produces::ok(Err(anyhow::anyhow!(
+                    "Job {} is an upload and is not retryable, upload the files again",
+                    id
+                )))
+            }
+        };
+
+        log::info!("Retry job {}", id);
+        Produces::ok(Ok(self.enqueue(spec)))
+    }
+
+    pub async fn get(&mut self, id: JobId) -> ActorResult<Option<Job>> {
+        Produces::ok(self.jobs.get(&id).cloned())
+    }
+
+    /// Jobs matching `filter`, newest first.
+    pub async fn list(&mut self, filter: JobFilter) -> ActorResult<Vec<Job>> {
+        let jobs = self
+            .jobs
+            .values()
+            .rev()
+            .filter(|job| filter.matches(job))
+            .take(filter.limit.unwrap_or(50))
+            .cloned()
+            .collect();
+
+        Produces::ok(jobs)
+    }
+
+    pub async fn stats(&mut self) -> ActorResult<JobStats> {
+        let mut states = BTreeMap::new();
+        for job in self.jobs.values() {
+            *states.entry(job.state).or_insert(0) += 1;
+        }
+
+        Produces::ok(JobStats {
+            queued: self.queue.len(),
+            running: self.running,
+            states,
+        })
+    }
+
+    async fn update(&mut self, id: JobId, update: JobUpdate) -> ActorResult<()> {
+        if let Some(job) = self.jobs.get_mut(&id) {
+            match update {
+                JobUpdate::State(state) => job.set_state(state),
+                JobUpdate::Failed(error) => {
+                    job.error = Some(error);
+                    job.set_state(JobState::Failed);
+                }
+                JobUpdate::Target(target, state) => job.update_target(target, state),
+            }
+
+            if job.state.is_finished() {
+                for waiter in self.waiters.remove(&id).into_iter().flatten() {
+                    let _ = waiter.send(Produces::Value(Some(job.clone())));
+                }
+            }
+        }
+
+        Produces::ok(())
+    }
+
+    /// Resolves once the job is finished, `None` for unknown jobs.
+    pub async fn wait(&mut self, id: JobId) -> ActorResult<Option<Job>> {
+        match self.jobs.get(&id) {
+            None => Produces::ok(None),
+            Some(job) if job.state.is_finished() => Produces::ok(Some(job.clone())),
+            Some(_) => {
+                let (sender, receiver) = oneshot::channel();
+                self.waiters.entry(id).or_default().push(sender);
+                Ok(Produces::Deferred(receiver))
+            }
+        }
+    }
+
+    async fn job_finished(&mut self) -> ActorResult<()> {
+        self.running -= 1;
+        self.start_jobs();
+
+        Produces::ok(())
+    }
+
+    fn start_jobs(&mut self) {
+        while self.running < self.max_running {
+            let job = match self.queue.pop() {
+                Some(job) => job,
+                None => break,
+            };
+
+            self.running += 1;
+            let processor = self.processor.clone();
+            self.addr.send_fut_with(|addr| async move {
+                run_job(job, processor, addr.clone()).await;
+                send!(addr.job_finished());
+            });
+        }
+    }
+
+    /// Drops the oldest finished jobs once more than `history` jobs are stored.
+    fn forget_old_jobs(&mut self) {
+        let excess = self.jobs.len().saturating_sub(self.history);
+        let finished: Vec<_> = self
+            .jobs
+            .values()
+            .filter(|job| job.state.is_finished())
+            .take(excess)
+            .map(|job| job.id)
+            .collect();
+
+        for id in finished {
+            self.jobs.remove(&id);
+            self.specs.remove(&id);
+        }
+    }
+}
+
+async fn run_job(
+    job: QueuedJob,
+    processor: Addr<RequestProcessorActor>,
+    jobs: WeakAddr<JobManagerActor>,
+) {
+    let QueuedJob { id, spec } = job;
+    log::info!("Start job {} for {}", id, spec.input.url());
+
+    let mut request = match spec.input {
+        JobInput::Link(link) => {
+            send!(jobs.update(id, JobUpdate::State(JobState::Fetching)));
+            let request = link
+                .fetch()
+                .await
+                .on_error(|e| log::error!("Error on fetch {}: {:#}", link.url, e));
+
+            match request {
+                Ok(request) => request,
+                Err(e) => {
+                    send!(jobs.update(id, JobUpdate::Failed(format!("{:#}", e))));
+                    return;
+                }
+            }
+        }
+        JobInput::Upload(request) => request,
+    };
+    send!(jobs.update(id, JobUpdate::State(JobState::Fetched)));
+
+    if spec.caption.is_some() {
+        request.caption = spec.caption;
+    }
+    request.tags.extend(spec.tags);
+
+    let deliveries = match call!(processor.handle_request(request, spec.targets)).await {
+        Ok(deliveries) if !deliveries.is_empty() => deliveries,
+        _ => {
+            let error = "No target accepted the request".to_owned();
+            send!(jobs.update(id, JobUpdate::Failed(error)));
+            return;
+        }
+    };
+
+    send!(jobs.update(id, JobUpdate::State(JobState::Delivering)));
+    for delivery in &deliveries {
+        let update = JobUpdate::Target(delivery.target.clone(), TargetState::Delivering);
+        send!(jobs.update(id, update));
+    }
+
+    let mut pending: FuturesUnordered<_> = deliveries
+        .into_iter()
+        .map(|Delivery { target, result }| async move { (target, result.await) })
+        .collect();
+
+    while let Some((target, result)) = pending.next().await {
+        let state = match result {
+            Ok(Ok(())) => TargetState::Delivered,
+            Ok(Err(e)) => TargetState::Failed {
+                error: format!("{:#}", e),
+            },
+            Err(_) => TargetState::Failed {
+                error: "Target stopped before finishing the request".to_owned(),
+            },
+        };
+        log::info!("Job {} on {}: {:?}", id, target, state);
+        send!(jobs.update(id, JobUpdate::Target(target, state)));
+    }
+}
+
+/// Submits every link found in `text`, then reports through `reply` which jobs were accepted
+/// and, once each of them is finished, how the delivery went.
+pub async fn ingest_text<F, Fut>(
+    sources: &SourceRegistry,
+    jobs: &Addr<JobManagerActor>,
+    text: &str,
+    reply: F,
+) where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let links = sources.find_links(text);
+    if links.is_empty() {
+        reply("Unsupported: no supported link found".to_owned()).await;
+        return;
+    }
+
+    let mut accepted = Vec::new();
+    for link in links {
+        let url = link.url.clone();
+        if let Ok(id) = call!(jobs.submit(JobSpec::new(JobInput::Link(link)))).await {
+            accepted.push((id, url));
+        }
+    }
+
+    let text = accepted
+        .iter()
+        .map(|(id, url)| format!("Accepted job {}: {}", id, url))
+        .collect::<Vec<_>>()
+        .join("\n");
+    reply(text).await;
+
+    for (id, _) in accepted {
+        if let Ok(Some(job)) = call!(jobs.wait(id)).await {
+            reply(job.summary()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(targets: &[&str]) -> Job {
+        let now = Utc::now();
+        Job {
+            id: 1,
+            source: "test".to_owned(),
+            url: "https://example.com/1".to_owned(),
+            priority: 0,
+            state: JobState::Delivering,
+            error: None,
+            targets: targets
+                .iter()
+                .map(|target| (target.to_string(), TargetState::Delivering))
+                .collect(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn failed() -> TargetState {
+        TargetState::Failed {
+            error: "error".to_owned(),
+        }
+    }
+
+    #[test]
+    fn delivered_once_every_target_is() {
+        let mut job = job(&["telegram", "vk"]);
+
+        job.update_target("telegram".to_owned(), TargetState::Delivered);
+        assert_eq!(job.state, JobState::Delivering);
+
+        job.update_target("vk".to_owned(), TargetState::Delivered);
+        assert_eq!(job.state, JobState::Delivered);
+    }
+
+    #[test]
+    fn partially_delivered_when_some_targets_fail() {
+        let mut job = job(&["telegram", "vk"]);
+
+        job.update_target("vk".to_owned(), failed());
+        assert_eq!(job.state, JobState::Delivering);
+
+        job.update_target("telegram".to_owned(), TargetState::Delivered);
+        assert_eq!(job.state, JobState::PartiallyDelivered);
+    }
+
+    #[test]
+    fn failed_when_every_target_fails() {
+        let mut job = job(&["telegram", "vk"]);
+
+        job.update_target("telegram".to_owned(), failed());
+        job.update_target("vk".to_owned(), failed());
+        assert_eq!(job.state, JobState::Failed);
+    }
+
+    #[test]
+    fn queue_starts_higher_priority_first_then_oldest() {
+        let queued = |id: JobId, priority: i32| {
+            let link = Link::detached("test", "https://example.com/1");
+            let mut spec = JobSpec::new(JobInput::Link(link));
+            spec.priority = priority;
+            QueuedJob { id, spec }
+        };
+
+        let mut queue = BinaryHeap::new();
+        queue.push(queued(1, 0));
+        queue.push(queued(2, 5));
+        queue.push(queued(3, -1));
+        queue.push(queued(4, 5));
+        queue.push(queued(5, 0));
+
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop()).map(|job| job.id).collect();
+        assert_eq!(order, vec![2, 4, 1, 5, 3]);
+    }
+}
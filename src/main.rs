@@ -1,103 +1,179 @@
+use crate::api::AppState;
+use crate::artstation::ArtStationReceiveActor;
+use crate::auth::Authenticator;
+use crate::jobs::JobManagerActor;
 use crate::pixiv::PixivReceiveActor;
-use crate::processor::RequestProcessorActor;
+use crate::processor::{RequestProcessorActor, Target};
+use crate::reddit::RedditReceiveActor;
+use crate::ratelimit::RateLimiter;
+use crate::source::SourceRegistry;
 use crate::telegram::TelegramSenderActor;
+use crate::telegram_bot::TelegramBotActor;
+use crate::twitter::TwitterReceiveActor;
 use crate::vk::VkSenderActor;
 use act_zero::runtimes::tokio::spawn_actor;
-use act_zero::{send, upcast, Addr};
+use act_zero::upcast;
 use std::env;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use axum::extract::State;
-use axum::Router;
-use axum::routing::post;
 
+use crate::deviantart::DeviantArtReceiveActor;
+use crate::tumblr::TumblrReceiveActor;
+use crate::imgur::ImgurReceiveActor;
+use crate::generic::GenericReceiveActor;
+use crate::scrapers::ScraperReceiveActor;
 use crate::discord::DiscordWebhookActor;
+use crate::bluesky::BlueskyReceiveActor;
+use crate::danbooru::DanbooruReceiveActor;
+use crate::e621::E621ReceiveActor;
+use crate::fediverse::FediverseReceiveActor;
 use crate::gelbooru::GelbooruReceiveActor;
+use crate::moebooru::MoebooruReceiveActor;
 
+mod api;
+mod auth;
 mod config;
+mod jobs;
 mod pixiv;
 mod pixiv_api;
 mod processor;
+mod ratelimit;
 mod request;
+mod source;
 mod telegram;
+mod telegram_bot;
 mod utils;
 mod vk;
+mod vk_callback;
 mod discord;
+mod discord_interactions;
 mod gelbooru;
+mod danbooru;
+mod moebooru;
+mod e621;
+mod twitter;
+mod reddit;
+mod bluesky;
+mod fediverse;
+mod artstation;
+mod deviantart;
+mod tumblr;
+mod imgur;
+mod generic;
+mod scrapers;
+mod ugoira;
 
-async fn pixiv_handler(
-    State(app_state): State<Arc<AppState>>,
-    body: String,
-) -> &'static str {
-    if body.contains("pixiv.net") {
-        let regex = regex::Regex::new(r"https?://(www\.)?pixiv\.net/en/artworks/(?P<id>\d+)")
-            .expect("Error on compile regex");
-        let id = regex
-            .captures(&body)
-            .and_then(|c| c.name("id"))
-            .and_then(|m| m.as_str().parse::<i64>().ok());
-
-        if let Some(id) = id {
-            send!(app_state.pixiv_receiver.receive_illust(id));
-        }
-    }
+#[tokio::main]
+async fn main() {
+    env::set_var("RUST_LOG", "heroku_bot=trace,atc_zero=warn");
+    env_logger::init();
 
-    if body.contains("gelbooru.com") {
-        let regex = regex::Regex::new(r"id=(?P<id>\d+)")
-            .expect("Error on compile regex");
-        let id = regex
-            .captures(&body)
-            .and_then(|c| c.name("id"))
-            .and_then(|m| m.as_str().parse::<u64>().ok());
-        if let Some(id) = id {
-            send!(app_state.gelbooru_receiver.receive_id(id, body));
+    // Works offline and without the other settings, to validate definitions before deploying them
+    if env::args().nth(1).as_deref() == Some("check-scrapers") {
+        let results = scrapers::check(&config::get_scrapers());
+        for result in &results {
+            println!("{}", result);
         }
+        std::process::exit(if results.iter().any(scrapers::CheckResult::is_failed) { 1 } else { 0 });
     }
 
-    "Ok"
-}
-
-pub struct AppState {
-    pixiv_receiver: Addr<PixivReceiveActor>,
-    gelbooru_receiver: Addr<GelbooruReceiveActor>,
-}
-
-#[tokio::main]
-async fn main() {
     let config = config::get_config();
-    env::set_var("RUST_LOG", "heroku_bot=trace,atc_zero=warn");
-    env_logger::init();
+
+    if env::args().nth(1).as_deref() == Some("register-discord-commands") {
+        discord_interactions::register_commands(&config)
+            .await
+            .expect("Error on register Discord commands");
+        return;
+    }
 
     log::info!("start bot");
 
     let mut targets = vec![];
 
     let telegram_target = spawn_actor(TelegramSenderActor::new(config.clone()));
-    targets.push(upcast!(telegram_target));
+    targets.push(Target::new("telegram", upcast!(telegram_target)));
 
     let vk_target = spawn_actor(VkSenderActor::new(config.clone()));
-    targets.push(upcast!(vk_target));
+    targets.push(Target::new("vk", upcast!(vk_target)));
 
     if config.discord_webhook.is_some() {
         let discord_target = spawn_actor(DiscordWebhookActor::new(config.clone()));
-        targets.push(upcast!(discord_target));
+        targets.push(Target::new("discord", upcast!(discord_target)));
     }
 
+    let target_names = targets.iter().map(|t| t.name.clone()).collect();
     let processor = spawn_actor(RequestProcessorActor::new(targets));
+    let jobs = spawn_actor(JobManagerActor::new(
+        processor,
+        config.max_running_jobs,
+        config.job_history,
+    ));
+
+    let mut sources = SourceRegistry::new();
+    sources.register(PixivReceiveActor::new(config.clone()).await);
+    for instance in &config.gelbooru_instances {
+        sources.register(GelbooruReceiveActor::new(instance.clone()));
+    }
+    sources.register(DanbooruReceiveActor::new(config.clone()));
+    for instance in &config.moebooru_instances {
+        sources.register(MoebooruReceiveActor::new(&config, instance.clone()));
+    }
+    sources.register(E621ReceiveActor::new(config.clone()));
+    sources.register(TwitterReceiveActor::new(config.clone()));
+    sources.register(RedditReceiveActor::new(config.clone()));
+    sources.register(BlueskyReceiveActor::new(config.clone()));
+    sources.register(ArtStationReceiveActor::new(config.clone()));
+    sources.register(DeviantArtReceiveActor::new(config.clone()));
+    if config.tumblr_api_key.is_some() {
+        sources.register(TumblrReceiveActor::new(config.clone()));
+    }
+    if config.imgur_client_id.is_some() {
+        sources.register(ImgurReceiveActor::new(config.clone()));
+    }
+    for definition in &config.scrapers {
+        let scraper = ScraperReceiveActor::new(definition)
+            .unwrap_or_else(|e| panic!("Invalid {} scraper: {:#}", definition.name, e));
+        sources.register(scraper);
+    }
+    // Recognizes post links on any host, so the sources of specific sites go first
+    sources.register(FediverseReceiveActor::new());
+    // Claims every link, so it goes last
+    if config.generic_enabled {
+        sources.register(GenericReceiveActor::new(config.clone()));
+    }
+    let sources = Arc::new(sources);
 
-    let pixiv_receiver =
-        spawn_actor(PixivReceiveActor::new(config.clone(), processor.clone()).await);
-
-    let gelbooru_receiver = spawn_actor(GelbooruReceiveActor::new(config.clone(), processor.clone()));
+    if !config.telegram_allowed_users.is_empty() {
+        spawn_actor(TelegramBotActor::new(&config, sources.clone(), jobs.clone()));
+    }
 
     let port = env::var("PORT")
         .unwrap_or("8080".to_owned())
         .parse()
         .expect("not number");
 
-    let app = Router::new()
-        .route("/post", post(pixiv_handler))
-        .with_state(Arc::new(AppState { pixiv_receiver, gelbooru_receiver }));
+    let app = api::router(
+        AppState {
+            sources: sources.clone(),
+            jobs: jobs.clone(),
+            targets: target_names,
+            limiter: Arc::new(RateLimiter::new(&config)),
+        },
+        Authenticator::new(
+            config.clients.clone(),
+            config.max_upload_size,
+            config.api_auth_disabled,
+        ),
+        config.max_upload_size,
+    )
+    .merge(vk_callback::router(&config, sources.clone(), jobs.clone()))
+    .merge(discord_interactions::router(&config, sources, jobs));
 
     let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
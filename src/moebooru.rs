@@ -0,0 +1,186 @@
+use crate::config::{Config, MoebooruInstance};
+use crate::request::{Image, ImageRequest, ImageRequestBody};
+use crate::source::{host_pattern, FetchResult, LinkPattern, Source};
+use crate::utils::ResultExtension;
+use act_zero::{Actor, ActorResult, Produces};
+use futures::future::join_all;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Post {
+    id: u64,
+    file_url: Option<String>,
+    #[serde(default)]
+    file_size: u64,
+    /// JPEG version of PNG originals, the original itself otherwise.
+    jpeg_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Pool {
+    /// Posts in page order.
+    posts: Vec<Post>,
+}
+
+/// Receives posts and pools from a site running Moebooru.
+pub struct MoebooruReceiveActor {
+    client: reqwest::Client,
+    instance: MoebooruInstance,
+    large_file_threshold: u64,
+}
+
+impl Actor for MoebooruReceiveActor {}
+
+impl MoebooruReceiveActor {
+    pub fn new(config: &Config, instance: MoebooruInstance) -> Self {
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("heroku_bot/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .expect("Error on build client");
+        let instance = MoebooruInstance {
+            base_url: instance.base_url.trim_end_matches('/').to_owned(),
+            ..instance
+        };
+
+        Self {
+            client,
+            instance,
+            large_file_threshold: config.large_file_threshold,
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> anyhow::Result<T> {
+        Ok(self
+            .client
+            .get(format!("{}/{}", self.instance.base_url, path))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    async fn download(&self, post: &Post) -> anyhow::Result<Image> {
+        let too_large = post.file_size > self.large_file_threshold;
+        let url = match (&post.file_url, &post.jpeg_url) {
+            (Some(_), Some(jpeg)) if too_large => jpeg,
+            (Some(url), _) => url,
+            (None, _) => anyhow::bail!("Post {} has no file available", post.id),
+        };
+
+        let data = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+            .on_error(|_| log::error!("Error on download image"))?;
+
+        Ok(Image::new(
+            url.rsplit('/').next().unwrap().to_string(),
+            data.as_ref().into(),
+        ))
+    }
+
+    async fn receive_post(&mut self, id: u64, url: String) -> FetchResult {
+        log::info!("Start {} process post {}", self.instance.name, id);
+
+        let posts: Vec<Post> = self
+            .get(&format!("post.json?tags=id:{}", id))
+            .await
+            .on_error(|_| log::error!("Error on request post"))?;
+        let post = posts
+            .into_iter()
+            .find(|post| post.id == id)
+            .ok_or_else(|| anyhow::anyhow!("Post {} not found", id))?;
+        let image = self.download(&post).await?;
+
+        Ok(ImageRequest::new(url, ImageRequestBody::SingleImage { image }))
+    }
+
+    async fn receive_pool(&mut self, id: u64, url: String) -> FetchResult {
+        log::info!("Start {} process pool {}", self.instance.name, id);
+
+        let pool: Pool = self
+            .get(&format!("pool/show.json?id={}", id))
+            .await
+            .on_error(|_| log::error!("Error on request pool"))?;
+        let images: Vec<_> = join_all(pool.posts.iter().map(|post| self.download(post)))
+            .await
+            .into_iter()
+            .filter_map(|r| r.on_error(|e| log::error!("{}", e)).ok())
+            .collect();
+
+        log::info!("Downloaded {} images", images.len());
+
+        let body = ImageRequestBody::from_images(images)
+            .ok_or_else(|| anyhow::anyhow!("Pool {} has no downloadable posts", id))?;
+        Ok(ImageRequest::new(url, body))
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for MoebooruReceiveActor {
+    fn name(&self) -> &str {
+        &self.instance.name
+    }
+
+    fn link_patterns(&self) -> Vec<LinkPattern> {
+        let host = self
+            .instance
+            .host
+            .clone()
+            .unwrap_or_else(|| host_pattern(&self.instance.base_url));
+
+        vec![
+            LinkPattern::new(
+                &format!(r"https?://(?:www\.)?(?:{})/post/show/(?P<id>\d+)", host),
+                &format!("{}/post/show/$id", self.instance.base_url),
+            ),
+            LinkPattern::new(
+                &format!(r"https?://(?:www\.)?(?:{})/pool/show/(?P<id>\d+)", host),
+                &format!("{}/pool/show/$id", self.instance.base_url),
+            ),
+        ]
+    }
+
+    async fn fetch(&mut self, url: String) -> ActorResult<FetchResult> {
+        let link = url
+            .strip_prefix(&self.instance.base_url)
+            .and_then(|path| path.trim_start_matches('/').split_once("/show/"))
+            .and_then(|(kind, id)| Some((kind.to_owned(), id.parse().ok()?)));
+
+        Produces::ok(match link {
+            Some((kind, id)) if kind == "post" => self.receive_post(id, url).await,
+            Some((kind, id)) if kind == "pool" => self.receive_pool(id, url).await,
+            _ => Err(anyhow::anyhow!("{} is not a {} post or pool", url, self.instance.name)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::source::canonical_links;
+
+    #[test]
+    fn recognizes_posts_and_pools() {
+        let config = test_config(&[]);
+        let yandere = MoebooruReceiveActor::new(&config, config.moebooru_instances[0].clone());
+        let konachan = MoebooruReceiveActor::new(&config, config.moebooru_instances[1].clone());
+
+        assert_eq!(
+            canonical_links(&yandere, "https://yande.re/post/show/123 https://yande.re/pool/show/45"),
+            vec!["https://yande.re/post/show/123", "https://yande.re/pool/show/45"]
+        );
+        assert_eq!(
+            canonical_links(&konachan, "http://konachan.net/post/show/123/sky-clouds"),
+            vec!["https://konachan.com/post/show/123"]
+        );
+        assert!(canonical_links(&yandere, "https://konachan.com/post/show/123").is_empty());
+    }
+}
@@ -1,31 +1,43 @@
 use crate::config::Config;
-use crate::processor::RequestProcessorActor;
 use crate::request::{Image, ImageRequest, ImageRequestBody};
+use crate::source::{FetchResult, LinkPattern, Source};
 use crate::utils::ResultExtension;
-use act_zero::{Actor, ActorResult, Addr, Produces};
+use act_zero::{Actor, ActorResult, Produces};
 use futures::future::join_all;
 use std::sync::Arc;
 use crate::pixiv_api::PixivClient;
+use crate::ugoira;
 
 pub struct PixivReceiveActor {
     client: PixivClient,
-    processor: Addr<RequestProcessorActor>,
 }
 
 impl Actor for PixivReceiveActor {}
 
 impl PixivReceiveActor {
-    pub async fn new(_config: Arc<Config>, processor: Addr<RequestProcessorActor>) -> Self {
+    pub async fn new(_config: Arc<Config>) -> Self {
         let mut client = PixivClient::new();
         let _ = client
             .auth(&_config.pixiv_refresh)
             .await
             .unwrap();
 
-        Self { client, processor }
+        Self { client }
     }
 
-    pub async fn receive_illust(&mut self, id: i64) -> ActorResult<()> {
+    async fn resolve_short_link(&self, url: &str) -> anyhow::Result<i64> {
+        log::info!("Resolve {}", url);
+        let resolved = reqwest::get(url)
+            .await
+            .on_error(|_| log::error!("Error on resolve short link"))?
+            .url()
+            .to_string();
+
+        parse_illust_id(&resolved)
+            .ok_or_else(|| anyhow::anyhow!("{} does not point to an illust", url))
+    }
+
+    async fn receive_illust(&mut self, id: i64) -> FetchResult {
         log::info!("Start process {}", id);
         let illust = self
             .client
@@ -33,6 +45,10 @@ impl PixivReceiveActor {
             .await
             .on_error(|_| log::error!("Error on get illust"))?;
         log::info!("{:?}", &illust);
+        if illust.is_ugoira() {
+            return self.receive_ugoira(id).await;
+        }
+
         let images: Vec<_> = join_all(
             illust
                 .links()
@@ -42,29 +58,150 @@ impl PixivReceiveActor {
         .await
         .into_iter()
         .filter_map(|r| r.ok())
-        .map(|(filename, data)| Image {
-            filename,
-            data: data.into(),
-        })
+        .map(|(filename, data)| Image::new(filename, data.into()))
         .collect();
 
         log::info!("Downloaded {} images", images.len());
 
-        let req = ImageRequest {
-            source: format!("https://www.pixiv.net/en/artworks/{}", id),
-            body: if images.len() > 1 {
+        Ok(ImageRequest::new(
+            format!("https://www.pixiv.net/en/artworks/{}", id),
+            if images.len() > 1 {
                 ImageRequestBody::Album { images }
             } else if images.len() == 1 {
                 ImageRequestBody::SingleImage {
                     image: images.into_iter().next().unwrap(),
                 }
             } else {
-                Err("Downloaded incorrect images")?
+                anyhow::bail!("Downloaded incorrect images")
+            },
+        ))
+    }
+
+    /// Assembles the frames of an animated work into a GIF, and an MP4 when ffmpeg is available.
+    async fn receive_ugoira(&mut self, id: i64) -> FetchResult {
+        log::info!("Start ugoira process {}", id);
+        let metadata = self
+            .client
+            .get_ugoira_metadata(id)
+            .await
+            .on_error(|_| log::error!("Error on get ugoira metadata"))?;
+
+        let mut archive = None;
+        for url in metadata.zip_urls() {
+            match self.client.download(&url).await {
+                Ok((_, data)) => {
+                    archive = Some(data);
+                    break;
+                }
+                Err(e) => log::warn!("Error on download {}: {}", url, e),
+            }
+        }
+        let archive = archive.ok_or_else(|| anyhow::anyhow!("No frames of ugoira {} downloaded", id))?;
+
+        let frames = ugoira::unpack(&archive, metadata.frames())?;
+        log::info!("Unpacked {} frames of {}", frames.len(), id);
+
+        let mp4 = ugoira::encode_mp4(&frames, &id.to_string())
+            .await
+            .on_error(|e| log::warn!("Send ugoira {} without MP4: {:#}", id, e))
+            .ok();
+        let gif = tokio::task::spawn_blocking(move || ugoira::encode_gif(&frames))
+            .await?
+            .on_error(|_| log::error!("Error on encode gif"))?;
+
+        Ok(ImageRequest::new(
+            format!("https://www.pixiv.net/en/artworks/{}", id),
+            ImageRequestBody::SingleImage {
+                image: Image::animation(
+                    format!("{}_ugoira.gif", id),
+                    gif.into(),
+                    mp4.map(Into::into),
+                ),
+            },
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for PixivReceiveActor {
+    fn name(&self) -> &str {
+        "pixiv"
+    }
+
+    /// Recognizes `artworks` pages (with or without a language prefix), legacy
+    /// `member_illust.php` pages, direct `i.pximg.net` image urls and `pixiv.me` short links.
+    fn link_patterns(&self) -> Vec<LinkPattern> {
+        let canonical = "https://www.pixiv.net/en/artworks/$id";
+        vec![
+            LinkPattern::new(
+                r"https?://(?:www\.)?pixiv\.net/(?:[a-z]{2}/)?artworks/(?P<id>\d+)",
+                canonical,
+            ),
+            LinkPattern::new(
+                r"https?://(?:www\.)?pixiv\.net/member_illust\.php\?\S*?\billust_id=(?P<id>\d+)\S*",
+                canonical,
+            ),
+            LinkPattern::new(
+                r"https?://i\.pximg\.net/\S*?/(?P<id>\d+)_(?:p\d+|ugoira)\S*",
+                canonical,
+            ),
+            LinkPattern::new(r"https?://(?:www\.)?pixiv\.me/[\w.-]+", "$0"),
+        ]
+    }
+
+    async fn fetch(&mut self, url: String) -> ActorResult<FetchResult> {
+        let id = match parse_illust_id(&url) {
+            Some(id) => id,
+            None => match self.resolve_short_link(&url).await {
+                Ok(id) => id,
+                Err(e) => return Produces::ok(Err(e)),
             },
         };
 
-        act_zero::send!(self.processor.handle_request(req));
+        Produces::ok(self.receive_illust(id).await)
+    }
+}
+
+fn parse_illust_id(url: &str) -> Option<i64> {
+    let (_, rest) = url.split_once("/artworks/")?;
+    rest.split(|c: char| !c.is_ascii_digit()).next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::canonical_links;
+
+    #[test]
+    fn recognizes_pixiv_links() {
+        let source = PixivReceiveActor { client: PixivClient::new() };
+        let canonical = "https://www.pixiv.net/en/artworks/12345";
+
+        for link in [
+            "https://www.pixiv.net/artworks/12345",
+            "https://pixiv.net/en/artworks/12345",
+            "http://www.pixiv.net/jp/artworks/12345#big_0",
+            "https://www.pixiv.net/member_illust.php?mode=medium&illust_id=12345",
+            "https://www.pixiv.net/member_illust.php?illust_id=12345&mode=manga",
+            "https://i.pximg.net/img-original/img/2024/01/01/00/00/00/12345_p0.png",
+            "https://i.pximg.net/img-zip-ugoira/img/2024/01/01/00/00/00/12345_ugoira1920x1080.zip",
+        ]
+        .iter()
+        {
+            assert_eq!(canonical_links(&source, link), vec![canonical], "{}", link);
+        }
+
+        assert_eq!(
+            canonical_links(&source, "see https://pixiv.me/some-artist now"),
+            vec!["https://pixiv.me/some-artist"]
+        );
+        assert!(canonical_links(&source, "https://www.pixiv.net/users/12345").is_empty());
+    }
 
-        Produces::ok(())
+    #[test]
+    fn parses_illust_ids() {
+        assert_eq!(parse_illust_id("https://www.pixiv.net/en/artworks/12345"), Some(12345));
+        assert_eq!(parse_illust_id("https://www.pixiv.net/artworks/12345?p=1"), Some(12345));
+        assert_eq!(parse_illust_id("https://www.pixiv.net/users/12345"), None);
     }
 }
@@ -1,10 +1,7 @@
-#![allow(non_camel_case_types)]
-
 use chrono::prelude::*;
 use reqwest::{header, Client};
 use serde::{Deserialize, Serialize};
-use serde_json::{Number, Value};
-use std::collections::HashMap;
+use serde_json::Number;
 use chrono::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -51,83 +48,6 @@ pub struct PixivRequestData {
     access_token: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub enum Restrict {
-    Public,
-    Private,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub enum Mode {
-    day,
-    week,
-    month,
-    day_male,
-    day_female,
-    week_original,
-    week_rookie,
-    day_r18,
-    day_male_r18,
-    day_female_r18,
-    week_r18,
-    week_r18g,
-    day_manga,
-    week_manga,
-    month_manga,
-    week_rookie_manga,
-    day_r18_manga,
-    week_r18_manga,
-    week_r18g_manga,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum SearchTarget {
-    partial_match_for_tags,
-    exact_match_for_tags,
-    title_and_caption,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum Sort {
-    date_desc,
-    date_asc,
-    popular_desc,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct PixivParams {
-    user_id: Option<Number>,
-    #[serde(rename = "type")]
-    r#type: Option<String>,
-    filter: Option<String>,
-    restrict: Option<Restrict>,
-    illust_id: Option<Number>,
-    content_type: Option<String>,
-    include_total_comments: Option<bool>,
-    include_ranking_label: Option<bool>,
-    include_ranking_illusts: Option<bool>,
-    include_ranking_novels: Option<bool>,
-    mode: Option<Mode>,
-    word: Option<String>,
-    search_target: Option<SearchTarget>,
-    sort: Option<Sort>,
-    start_date: Option<String>,
-    end_date: Option<String>,
-    offset: Option<Number>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct PixivFetchOptions {
-    data: Option<PixivParams>,
-    method: Option<String>,
-    headers: HashMap<String, String>,
-    params: Option<PixivParams>,
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct ProfileImageUrl {
@@ -145,72 +65,6 @@ pub struct PixivUser {
     is_followed: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct Profile {
-    webpage: String,
-    gender: String,
-    birth: String,
-    birth_day: String,
-    birth_year: Number,
-    region: String,
-    address_id: Number,
-    country_code: String,
-    job: String,
-    job_id: Number,
-    total_follow_users: Number,
-    total_mypixiv_users: Number,
-    total_illusts: Number,
-    total_manga: Number,
-    total_novels: Number,
-    total_illust_bookmarks_public: Number,
-    total_illust_series: Number,
-    background_image_url: String,
-    twitter_account: String,
-    twitter_url: String,
-    pawoo_url: String,
-    is_premium: bool,
-    is_using_custom_profile_image: bool,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct ProfilePublicity {
-    gender: String,
-    region: String,
-    birth_day: String,
-    birth_year: String,
-    job: String,
-    pawoo: bool,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct Workspace {
-    pc: String,
-    monitor: String,
-    tool: String,
-    scanner: String,
-    tablet: String,
-    mouse: String,
-    printer: String,
-    desktop: String,
-    music: String,
-    desk: String,
-    chair: String,
-    comment: String,
-    workspace_image_url: Option<String>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct PixivUserDetail {
-    user: PixivUser,
-    profile: Profile,
-    profile_publicity: ProfilePublicity,
-    workspace: Workspace,
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct PixivIllustDetail {
@@ -218,6 +72,11 @@ pub struct PixivIllustDetail {
 }
 
 impl PixivIllustDetail {
+    /// Animated works, whose links only hold the first frame.
+    pub fn is_ugoira(&self) -> bool {
+        self.illust.r#type == "ugoira"
+    }
+
     pub fn links(&self) -> impl Iterator<Item = &str> {
         self.illust
             .meta_pages
@@ -232,59 +91,44 @@ impl PixivIllustDetail {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
-pub struct PixivIllustSearch {
-    illusts: Vec<PixivIllust>,
-    next_url: Option<String>,
-    search_span_limit: Option<Number>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct UserPreviews {
-    user: PixivUser,
-    illusts: Vec<PixivIllust>,
-    novels: Vec<PixivNovel>,
-    is_muted: bool,
+pub struct PixivUgoiraMetadata {
+    ugoira_metadata: UgoiraMetadata,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct PixivUserSearch {
-    user_previews: Vec<UserPreviews>,
-    next_url: Option<String>,
-}
+impl PixivUgoiraMetadata {
+    /// Archive of the frames in the largest size Pixiv keeps, the api only names the 600x600 one.
+    pub fn zip_urls(&self) -> impl Iterator<Item = String> + '_ {
+        let medium = &self.ugoira_metadata.zip_urls.medium;
+        std::iter::once(medium.replace("600x600", "1920x1080")).chain(std::iter::once(medium.clone()))
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct PixivCommentSearch {
-    total_comments: Number,
-    comments: Vec<PixivComment>,
-    next_url: Option<String>,
+    /// File name in the archive and delay in milliseconds of every frame.
+    pub fn frames(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.ugoira_metadata
+            .frames
+            .iter()
+            .map(|frame| (frame.file.as_str(), frame.delay))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
-pub struct PixivNovelSearch {
-    novels: Vec<PixivNovel>,
-    next_url: Option<String>,
-    privacy_policy: Option<Value>,
-    search_span_limit: Option<Number>,
+pub struct UgoiraMetadata {
+    zip_urls: UgoiraZipUrls,
+    frames: Vec<UgoiraFrame>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
-pub struct PixivBookmarkSearch {
-    bookmark_tags: Vec<PixivTag>,
-    next_url: Option<String>,
+pub struct UgoiraZipUrls {
+    medium: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
-pub struct PixivMangaSearch {
-    illusts: Vec<PixivManga>,
-    ranking_illusts: Vec<Value>,
-    privacy_policy: Value,
-    next_url: Option<String>,
+pub struct UgoiraFrame {
+    file: String,
+    delay: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -355,90 +199,6 @@ pub struct PixivMetaPage {
     image_urls: MetaImageUrls,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct PixivComment {
-    id: Number,
-    comment: String,
-    date: String,
-    user: PixivUser,
-    parent_comment: Value,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct PixivNovel {
-    id: Number,
-    title: String,
-    caption: String,
-    restrict: Number,
-    x_restrict: Number,
-    image_urls: ImageUrls,
-    create_date: String,
-    tags: Vec<PixivTag>,
-    page_count: Number,
-    text_length: Number,
-    user: PixivUser,
-    series: Value,
-    is_bookmarked: bool,
-    total_bookmarks: Number,
-    total_view: Number,
-    visible: bool,
-    total_comments: Number,
-    is_muted: bool,
-    is_mypixiv_only: bool,
-    is_xrestricted: bool,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct PixivManga {
-    id: Number,
-    title: String,
-    #[serde(rename = "type")]
-    r#type: String,
-    image_urls: ImageUrls,
-    caption: String,
-    restrict: Number,
-    user: PixivUser,
-    tags: Vec<PixivTag>,
-    tools: Vec<String>,
-    create_date: String,
-    page_count: String,
-    width: Number,
-    height: Number,
-    sanity_level: Number,
-    x_restrict: Number,
-    series: Option<Value>,
-    meta_single_page: Value,
-    meta_pages: Vec<PixivMetaPage>,
-    total_view: Number,
-    total_bookmarks: Number,
-    is_bookmarked: bool,
-    visible: bool,
-    is_muted: bool,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct PixivTrendTags {
-    trend_tags: Vec<PixivTag>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct PixivAutoComplete {
-    search_auto_complete_keywords: Vec<String>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct PixivBookmarkDetail {
-    is_bookmarked: bool,
-    tags: Vec<PixivTag>,
-    restrict: String,
-}
-
 const CLIENT_ID: &str = "MOBrBDS8blbauoSck0ZfDbtuzpyT";
 const CLIENT_SECRET: &str = "lsACyCD94FhDUtGTXi3QzcFE2uU1hqtDaKeqrdwj";
 const HASH_SECRET: &str = "28c1fdd170a5204386cb1313c7077b34f83e4aaf4aa829ce78c231e05b0bae2c";
@@ -498,7 +258,7 @@ impl PixivClient {
     }
 
     async fn check_auth(&mut self) {
-        if let Some(auth_time) = self.auth_date.clone() {
+        if let Some(auth_time) = self.auth_date {
             if Utc::now().naive_utc() - auth_time < Duration::minutes(50) {
                 return;
             }
@@ -537,13 +297,34 @@ impl PixivClient {
         self.client.execute(request).await?.json().await
     }
 
+    pub async fn get_ugoira_metadata(&mut self, illust_id: i64) -> reqwest::Result<PixivUgoiraMetadata> {
+        self.check_auth().await;
+
+        self.client
+            .get("https://app-api.pixiv.net/v1/ugoira/metadata")
+            .query(&[("illust_id", illust_id.to_string())])
+            .bearer_auth(
+                self.client_data
+                    .as_ref()
+                    .expect("Need auth")
+                    .access_token
+                    .as_str(),
+            )
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+
     pub async fn download(&self, url: &str) -> reqwest::Result<(String, Vec<u8>)> {
         let res = self
             .client
             .get(url)
             .header("Referer", "https://app-api.pixiv.net")
             .send()
-            .await?;
+            .await?
+            .error_for_status()?;
         let filename = res.url().path().rsplit("/").next().expect("Need file name");
         Ok((filename.to_owned(), res.bytes().await?.to_vec()))
     }
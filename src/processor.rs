@@ -1,27 +1,63 @@
-use crate::request::{ImageRequest, ImageSender};
-use act_zero::{send, Actor, ActorResult, Addr, Produces};
+use crate::request::{DeliveryResult, ImageRequest, ImageSender};
+use act_zero::{call, Actor, ActorResult, Addr, Produces};
 use std::sync::Arc;
 
+pub struct Target {
+    pub name: String,
+    pub addr: Addr<dyn ImageSender + 'static>,
+}
+
+impl Target {
+    pub fn new(name: &str, addr: Addr<dyn ImageSender + 'static>) -> Self {
+        Self {
+            name: name.to_owned(),
+            addr,
+        }
+    }
+}
+
+/// A request handed over to a target, resolves once the target is done with it.
+pub struct Delivery {
+    pub target: String,
+    pub result: Produces<DeliveryResult>,
+}
+
 pub struct RequestProcessorActor {
-    targets: Vec<Addr<dyn ImageSender + 'static>>,
+    targets: Vec<Target>,
 }
 
 impl Actor for RequestProcessorActor {}
 
 impl RequestProcessorActor {
-    pub fn new(targets: Vec<Addr<dyn ImageSender + 'static>>) -> Self {
+    pub fn new(targets: Vec<Target>) -> Self {
         Self { targets }
     }
 
-    pub async fn handle_request(&mut self, request: ImageRequest) -> ActorResult<()> {
+    /// Sends `request` to the targets named in `targets`, or to every target when it is `None`.
+    pub async fn handle_request(
+        &mut self,
+        request: ImageRequest,
+        targets: Option<Vec<String>>,
+    ) -> ActorResult<Vec<Delivery>> {
         log::info!("Start process request from {}", &request.source);
 
         let request = Arc::new(request);
+        let mut deliveries = Vec::new();
 
         for target in &self.targets {
-            send!(target.handle_request(request.clone()));
+            let selected = match &targets {
+                Some(targets) => targets.contains(&target.name),
+                None => true,
+            };
+
+            if selected {
+                deliveries.push(Delivery {
+                    target: target.name.clone(),
+                    result: call!(target.addr.handle_request(request.clone())),
+                });
+            }
         }
 
-        Produces::ok(())
+        Produces::ok(deliveries)
     }
 }
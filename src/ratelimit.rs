@@ -0,0 +1,305 @@
+use crate::api::ApiError;
+use crate::auth::AuthenticatedClient;
+use crate::config::{ClientConfig, Config};
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use chrono::{NaiveDate, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Buckets untouched for this long are dropped.
+const IDLE_BUCKET_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Copy, Debug)]
+struct Limits {
+    burst: u32,
+    per_minute: u32,
+    daily_quota: Option<u64>,
+}
+
+struct Bucket {
+    limits: Limits,
+    tokens: f64,
+    updated: Instant,
+    day: NaiveDate,
+    used_today: u64,
+    accepted: u64,
+    rejected: u64,
+}
+
+impl Bucket {
+    fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            tokens: limits.burst as f64,
+            updated: Instant::now(),
+            day: Utc::now().date_naive(),
+            used_today: 0,
+            accepted: 0,
+            rejected: 0,
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let refill = now.duration_since(self.updated).as_secs_f64()
+            * self.limits.per_minute as f64
+            / 60.0;
+        self.tokens = (self.tokens + refill).min(self.limits.burst as f64);
+        self.updated = now;
+
+        let today = Utc::now().date_naive();
+        if today != self.day {
+            self.day = today;
+            self.used_today = 0;
+        }
+    }
+
+    /// Takes a token, or returns how long to wait before retrying.
+    fn take(&mut self) -> Result<(), Duration> {
+        self.refill();
+
+        if let Some(quota) = self.limits.daily_quota {
+            if self.used_today >= quota {
+                self.rejected += 1;
+                let midnight = (self.day + chrono::Duration::days(1))
+                    .and_hms_opt(0, 0, 0)
+                    .expect("Midnight is a valid time")
+                    .and_utc();
+                let wait = (midnight - Utc::now()).num_seconds().max(1) as u64;
+                return Err(Duration::from_secs(wait));
+            }
+        }
+
+        if self.tokens < 1.0 {
+            self.rejected += 1;
+            let wait = if self.limits.per_minute == 0 {
+                IDLE_BUCKET_LIFETIME.as_secs_f64()
+            } else {
+                (1.0 - self.tokens) * 60.0 / self.limits.per_minute as f64
+            };
+            return Err(Duration::from_secs(wait.ceil().max(1.0) as u64));
+        }
+
+        self.tokens -= 1.0;
+        self.used_today += 1;
+        self.accepted += 1;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RateLimitUsage {
+    pub key: String,
+    pub tokens: f64,
+    pub burst: u32,
+    pub per_minute: u32,
+    pub used_today: u64,
+    pub daily_quota: Option<u64>,
+    pub accepted: u64,
+    pub rejected: u64,
+}
+
+/// Token bucket per client, or per ip for anonymous requests, plus an optional daily quota.
+///
+/// Limits come from the `rate_limit_*` settings and can be overridden per client.
+pub struct RateLimiter {
+    defaults: Limits,
+    clients: HashMap<String, Limits>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &Config) -> Self {
+        let defaults = Limits {
+            burst: config.rate_limit_burst,
+            per_minute: config.rate_limit_per_minute,
+            daily_quota: config.daily_quota,
+        };
+        let clients = config
+            .clients
+            .iter()
+            .map(|client| (client.name.clone(), client_limits(client, defaults)))
+            .collect();
+
+        Self {
+            defaults,
+            clients,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn take(&self, key: String, limits: Limits) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.contains_key(&key) {
+            buckets.retain(|_, bucket| bucket.updated.elapsed() < IDLE_BUCKET_LIFETIME);
+        }
+
+        buckets
+            .entry(key)
+            .or_insert_with(|| Bucket::new(limits))
+            .take()
+    }
+
+    pub fn usage(&self) -> Vec<RateLimitUsage> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let mut usage: Vec<_> = buckets
+            .iter_mut()
+            .map(|(key, bucket)| {
+                bucket.refill();
+                RateLimitUsage {
+                    key: key.clone(),
+                    tokens: bucket.tokens,
+                    burst: bucket.limits.burst,
+                    per_minute: bucket.limits.per_minute,
+                    used_today: bucket.used_today,
+                    daily_quota: bucket.limits.daily_quota,
+                    accepted: bucket.accepted,
+                    rejected: bucket.rejected,
+                }
+            })
+            .collect();
+
+        usage.sort_by(|a, b| a.key.cmp(&b.key));
+        usage
+    }
+}
+
+fn client_limits(client: &ClientConfig, defaults: Limits) -> Limits {
+    Limits {
+        burst: client.rate_limit_burst.unwrap_or(defaults.burst),
+        per_minute: client.rate_limit_per_minute.unwrap_or(defaults.per_minute),
+        daily_quota: client.daily_quota.or(defaults.daily_quota),
+    }
+}
+
+pub async fn limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (key, limits) = match request.extensions().get::<AuthenticatedClient>() {
+        Some(AuthenticatedClient(name)) => (
+            format!("client:{}", name),
+            limiter.clients.get(name).copied().unwrap_or(limiter.defaults),
+        ),
+        None => (format!("ip:{}", client_ip(&request)), limiter.defaults),
+    };
+
+    match limiter.take(key.clone(), limits) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            log::warn!(
+                "Rate limited {} on {}, retry after {}s",
+                key,
+                request.uri(),
+                retry_after.as_secs()
+            );
+            let mut response =
+                ApiError::new(StatusCode::TOO_MANY_REQUESTS, "Too many requests").into_response();
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(retry_after.as_secs()),
+            );
+            response
+        }
+    }
+}
+
+/// The last `X-Forwarded-For` address when behind a proxy, the peer address otherwise.
+///
+/// The Heroku router appends the address it saw, earlier entries come from the client
+/// and can not be trusted.
+fn client_ip(request: &Request) -> String {
+    request
+        .headers()
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .rfind(|ip| !ip.is_empty())
+        .map(str::to_owned)
+        .or_else(|| {
+            request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(burst: u32, per_minute: u32, daily_quota: Option<u64>) -> Bucket {
+        Bucket::new(Limits {
+            burst,
+            per_minute,
+            daily_quota,
+        })
+    }
+
+    /// Pretends the bucket was last used `seconds` ago.
+    fn rewind(bucket: &mut Bucket, seconds: f64) {
+        bucket.updated -= Duration::from_secs_f64(seconds);
+    }
+
+    #[test]
+    fn allows_a_burst_then_waits_for_a_token() {
+        let mut bucket = bucket(2, 60, None);
+
+        assert_eq!(bucket.take(), Ok(()));
+        assert_eq!(bucket.take(), Ok(()));
+        assert_eq!(bucket.take(), Err(Duration::from_secs(1)));
+        assert_eq!((bucket.accepted, bucket.rejected), (2, 1));
+    }
+
+    #[test]
+    fn refills_with_time_up_to_the_burst() {
+        let mut bucket = bucket(3, 6, None);
+        for _ in 0..3 {
+            bucket.take().unwrap();
+        }
+
+        rewind(&mut bucket, 10.0);
+        assert_eq!(bucket.take(), Ok(()));
+        assert!(bucket.take().is_err());
+
+        rewind(&mut bucket, 3600.0);
+        bucket.refill();
+        assert_eq!(bucket.tokens, 3.0);
+    }
+
+    #[test]
+    fn stops_at_the_daily_quota() {
+        let mut bucket = bucket(5, 60, Some(2));
+
+        assert_eq!(bucket.take(), Ok(()));
+        assert_eq!(bucket.take(), Ok(()));
+        let wait = bucket.take().unwrap_err();
+        assert!(wait > Duration::from_secs(0) && wait <= Duration::from_secs(24 * 60 * 60));
+    }
+
+    #[test]
+    fn keys_anonymous_clients_on_the_address_the_proxy_saw() {
+        let request = Request::builder()
+            .header("x-forwarded-for", "1.1.1.1, 2.2.2.2")
+            .header("x-forwarded-for", "3.3.3.3")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        assert_eq!(client_ip(&request), "3.3.3.3");
+
+        let mut request = Request::new(axum::body::Body::empty());
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([4, 4, 4, 4], 1234))));
+        assert_eq!(client_ip(&request), "4.4.4.4");
+    }
+}
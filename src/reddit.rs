@@ -0,0 +1,317 @@
+use crate::config::Config;
+use crate::request::{Image, ImageRequest, ImageRequestBody};
+use crate::source::{FetchResult, LinkPattern, Source};
+use crate::utils::ResultExtension;
+use act_zero::{Actor, ActorResult, Produces};
+use futures::future::join_all;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+struct Listing {
+    data: ListingData,
+}
+
+#[derive(Deserialize)]
+struct ListingData {
+    children: Vec<Thing>,
+}
+
+#[derive(Deserialize)]
+struct Thing {
+    data: Post,
+}
+
+#[derive(Deserialize)]
+struct Post {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    over_18: bool,
+    url: Option<String>,
+    /// `image` for single image posts.
+    post_hint: Option<String>,
+    gallery_data: Option<GalleryData>,
+    #[serde(default)]
+    media_metadata: HashMap<String, MediaMetadata>,
+    /// Crossposts carry the media of the original post here.
+    #[serde(default)]
+    crosspost_parent_list: Vec<Post>,
+}
+
+#[derive(Deserialize)]
+struct GalleryData {
+    items: Vec<GalleryItem>,
+}
+
+#[derive(Deserialize)]
+struct GalleryItem {
+    media_id: String,
+}
+
+#[derive(Deserialize)]
+struct MediaMetadata {
+    /// `Image` or `AnimatedImage`.
+    e: Option<String>,
+    /// Mime type, like `image/png`.
+    m: Option<String>,
+    s: Option<MediaSource>,
+}
+
+#[derive(Deserialize)]
+struct MediaSource {
+    u: Option<String>,
+    gif: Option<String>,
+    mp4: Option<String>,
+}
+
+/// Receives image posts and galleries from Reddit.
+pub struct RedditReceiveActor {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl Actor for RedditReceiveActor {}
+
+impl RedditReceiveActor {
+    pub fn new(config: Arc<Config>) -> Self {
+        // Reddit throttles generic user agents
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("heroku_bot/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .expect("Error on build client");
+
+        Self {
+            client,
+            base_url: config.reddit_base_url.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    async fn download(&self, url: &str, video: bool) -> anyhow::Result<Image> {
+        let data = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+            .on_error(|_| log::error!("Error on download image"))?;
+
+        let path = url.split('?').next().unwrap();
+        let filename = path.rsplit('/').next().unwrap().to_string();
+        Ok(if video {
+            Image::video(filename, data.as_ref().into())
+        } else {
+            Image::new(filename, data.as_ref().into())
+        })
+    }
+
+    async fn receive_post(&mut self, id: &str, url: String) -> FetchResult {
+        log::info!("Start reddit process {}", id);
+
+        let listings: Vec<Listing> = self
+            .client
+            .get(format!("{}/comments/{}.json?raw_json=1", self.base_url, id))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .on_error(|_| log::error!("Error on request post"))?;
+        let post = listings
+            .into_iter()
+            .next()
+            .and_then(|listing| listing.data.children.into_iter().next())
+            .map(|thing| thing.data)
+            .ok_or_else(|| anyhow::anyhow!("Post {} not found", id))?;
+
+        let media = post
+            .crosspost_parent_list
+            .first()
+            .map(post_media)
+            .unwrap_or_else(|| post_media(&post));
+        let images: Vec<_> = join_all(media.iter().map(|(url, video)| self.download(url, *video)))
+            .await
+            .into_iter()
+            .filter_map(|r| r.on_error(|e| log::error!("{}", e)).ok())
+            .collect();
+
+        log::info!("Downloaded {} images", images.len());
+
+        let body = ImageRequestBody::from_images(images)
+            .ok_or_else(|| anyhow::anyhow!("Post {} has no images", id))?;
+        let mut request = ImageRequest::new(url, body);
+        request.caption = Some(post.title).filter(|title| !title.is_empty());
+        request.nsfw = post.over_18;
+        Ok(request)
+    }
+}
+
+/// Urls of the gallery items in order, or of the single image, flagged when a video.
+fn post_media(post: &Post) -> Vec<(String, bool)> {
+    if let Some(gallery) = &post.gallery_data {
+        return gallery
+            .items
+            .iter()
+            .filter_map(|item| gallery_media(&item.media_id, post.media_metadata.get(&item.media_id)?))
+            .collect();
+    }
+
+    post.url
+        .iter()
+        .filter(|url| {
+            post.post_hint.as_deref() == Some("image") || url.starts_with("https://i.redd.it/")
+        })
+        .map(|url| (url.clone(), false))
+        .collect()
+}
+
+fn gallery_media(id: &str, metadata: &MediaMetadata) -> Option<(String, bool)> {
+    let source = metadata.s.as_ref()?;
+    match metadata.e.as_deref() {
+        Some("AnimatedImage") => source
+            .mp4
+            .clone()
+            .map(|url| (url, true))
+            .or_else(|| source.gif.clone().map(|url| (url, false))),
+        _ => {
+            // Originals live on i.redd.it, previews are resized
+            let extension = metadata
+                .m
+                .as_deref()
+                .and_then(|mime| mime.strip_prefix("image/"))
+                .map(|extension| if extension == "jpeg" { "jpg" } else { extension });
+            match extension {
+                Some(extension) => Some((format!("https://i.redd.it/{}.{}", id, extension), false)),
+                None => source.u.clone().map(|url| (url, false)),
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for RedditReceiveActor {
+    fn name(&self) -> &str {
+        "reddit"
+    }
+
+    /// Recognizes post links, with or without the subreddit, and `redd.it` short links.
+    fn link_patterns(&self) -> Vec<LinkPattern> {
+        vec![
+            LinkPattern::new(
+                r"https?://(?:(?:www|old|new|np)\.)?reddit\.com/r/(?P<sub>\w+)/comments/(?P<id>[a-z0-9]+)",
+                "https://www.reddit.com/r/$sub/comments/$id",
+            ),
+            LinkPattern::new(
+                r"https?://(?:(?:www|old|new|np)\.)?reddit\.com/comments/(?P<id>[a-z0-9]+)",
+                "https://www.reddit.com/comments/$id",
+            ),
+            LinkPattern::new(
+                r"https?://redd\.it/(?P<id>[a-z0-9]+)",
+                "https://www.reddit.com/comments/$id",
+            ),
+        ]
+    }
+
+    async fn fetch(&mut self, url: String) -> ActorResult<FetchResult> {
+        let id = url
+            .split_once("/comments/")
+            .map(|(_, id)| id.to_owned())
+            .filter(|id| !id.is_empty());
+
+        Produces::ok(match id {
+            Some(id) => self.receive_post(&id, url).await,
+            None => Err(anyhow::anyhow!("{} is not a reddit post", url)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::source::canonical_links;
+
+    #[test]
+    fn recognizes_posts_and_short_links() {
+        let source = RedditReceiveActor::new(test_config(&[]));
+
+        assert_eq!(
+            canonical_links(
+                &source,
+                "https://old.reddit.com/r/pics/comments/abc123/a_title/ \
+                 https://www.reddit.com/comments/def456 https://redd.it/ghi789"
+            ),
+            vec![
+                "https://www.reddit.com/r/pics/comments/abc123",
+                "https://www.reddit.com/comments/def456",
+                "https://www.reddit.com/comments/ghi789",
+            ]
+        );
+        assert!(canonical_links(&source, "https://www.reddit.com/r/pics/").is_empty());
+    }
+
+    #[test]
+    fn gallery_keeps_the_order_of_its_items() {
+        let post: Post = serde_json::from_value(serde_json::json!({
+            "title": "Gallery",
+            "url": "https://www.reddit.com/gallery/abc123",
+            "gallery_data": {
+                "items": [
+                    { "media_id": "third" },
+                    { "media_id": "first" },
+                    { "media_id": "missing" },
+                    { "media_id": "second" },
+                ],
+            },
+            "media_metadata": {
+                "first": {
+                    "e": "Image",
+                    "m": "image/jpeg",
+                    "s": { "u": "https://preview.redd.it/first.jpg?width=640" },
+                },
+                "second": {
+                    "e": "AnimatedImage",
+                    "m": "image/gif",
+                    "s": {
+                        "gif": "https://i.redd.it/second.gif",
+                        "mp4": "https://preview.redd.it/second.gif?format=mp4",
+                    },
+                },
+                "third": {
+                    "e": "Image",
+                    "s": { "u": "https://preview.redd.it/third.png?width=640" },
+                },
+            },
+        }))
+        .unwrap();
+
+        assert_eq!(
+            post_media(&post),
+            vec![
+                ("https://preview.redd.it/third.png?width=640".to_owned(), false),
+                ("https://i.redd.it/first.jpg".to_owned(), false),
+                ("https://preview.redd.it/second.gif?format=mp4".to_owned(), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn single_image_needs_an_image_link() {
+        let post = |url: &str, hint: Option<&str>| -> Post {
+            serde_json::from_value(serde_json::json!({ "url": url, "post_hint": hint })).unwrap()
+        };
+
+        assert_eq!(
+            post_media(&post("https://i.redd.it/abc.png", None)),
+            vec![("https://i.redd.it/abc.png".to_owned(), false)]
+        );
+        assert_eq!(
+            post_media(&post("https://example.com/abc.png", Some("image"))),
+            vec![("https://example.com/abc.png".to_owned(), false)]
+        );
+        assert!(post_media(&post("https://example.com/article", Some("link"))).is_empty());
+    }
+}
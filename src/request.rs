@@ -1,23 +1,196 @@
 use act_zero::{Actor, ActorResult};
 use std::sync::Arc;
 
+#[derive(Clone)]
 pub struct ImageRequest {
     pub source: String,
+    pub caption: Option<String>,
+    pub tags: Vec<String>,
+    /// Sent as spoilers where the target supports it.
+    pub nsfw: bool,
     pub body: ImageRequestBody,
 }
 
+impl ImageRequest {
+    pub fn new(source: String, body: ImageRequestBody) -> Self {
+        Self {
+            source,
+            caption: None,
+            tags: Vec::new(),
+            nsfw: false,
+            body,
+        }
+    }
+
+    /// Text posted along with the images: caption, hashtags and the source link.
+    pub fn text(&self) -> String {
+        let tags = self
+            .tags
+            .iter()
+            .map(|tag| {
+                let tag: String = tag
+                    .chars()
+                    .map(|c| if c.is_alphanumeric() { c } else { '_' })
+                    .collect();
+                format!("#{}", tag)
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        self.caption
+            .iter()
+            .map(String::as_str)
+            .chain(Some(tags.as_str()).filter(|t| !t.is_empty()))
+            .chain(Some(self.source.as_str()).filter(|s| !s.is_empty()))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+#[derive(Clone)]
 pub enum ImageRequestBody {
     SingleImage { image: Image },
     Album { images: Vec<Image> },
 }
 
+impl ImageRequestBody {
+    /// `SingleImage` for one image, `Album` for more, `None` when there is nothing to send.
+    pub fn from_images(mut images: Vec<Image>) -> Option<Self> {
+        match images.len() {
+            0 => None,
+            1 => Some(ImageRequestBody::SingleImage {
+                image: images.remove(0),
+            }),
+            _ => Some(ImageRequestBody::Album { images }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaKind {
+    Still,
+    /// Sent as it is, senders must not decode it as an image.
+    Video,
+    /// Animated GIF, or an MP4 standing in for one, sent as it is like a video.
+    Animation,
+}
+
 #[derive(Clone)]
 pub struct Image {
     pub filename: String,
     pub data: Arc<[u8]>,
+    pub kind: MediaKind,
+    /// Description of the image, shown next to it where the target supports it.
+    pub alt: Option<String>,
+    /// MP4 version of an animation, smaller and preferred where the target plays it.
+    pub mp4: Option<Arc<[u8]>>,
 }
 
+impl Image {
+    pub fn new(filename: String, data: Arc<[u8]>) -> Self {
+        Self {
+            filename,
+            data,
+            kind: MediaKind::Still,
+            alt: None,
+            mp4: None,
+        }
+    }
+
+    pub fn video(filename: String, data: Arc<[u8]>) -> Self {
+        Self {
+            kind: MediaKind::Video,
+            ..Self::new(filename, data)
+        }
+    }
+
+    pub fn animation(filename: String, gif: Arc<[u8]>, mp4: Option<Arc<[u8]>>) -> Self {
+        Self {
+            kind: MediaKind::Animation,
+            mp4,
+            ..Self::new(filename, gif)
+        }
+    }
+
+    pub fn is_video(&self) -> bool {
+        self.kind == MediaKind::Video
+    }
+
+    pub fn is_animation(&self) -> bool {
+        self.kind == MediaKind::Animation
+    }
+}
+
+/// Outcome of sending a request to a target, errors are reported back to the job.
+pub type DeliveryResult = anyhow::Result<()>;
+
 #[async_trait::async_trait]
 pub trait ImageSender: Actor + Send {
-    async fn handle_request(&mut self, request: Arc<ImageRequest>) -> ActorResult<()>;
+    async fn handle_request(&mut self, request: Arc<ImageRequest>) -> ActorResult<DeliveryResult>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(name: &str) -> Image {
+        Image::new(name.to_owned(), Arc::from(&b"data"[..]))
+    }
+
+    #[test]
+    fn text_joins_caption_tags_and_source() {
+        let mut request = ImageRequest::new(
+            "https://example.com/1".to_owned(),
+            ImageRequestBody::SingleImage { image: image("1.png") },
+        );
+        request.caption = Some("Title".to_owned());
+        request.tags = vec!["blue sky".to_owned(), "fan-art".to_owned(), "猫".to_owned()];
+
+        assert_eq!(
+            request.text(),
+            "Title\n\n#blue_sky #fan_art #猫\n\nhttps://example.com/1"
+        );
+    }
+
+    #[test]
+    fn text_skips_missing_caption_and_tags() {
+        let mut request = ImageRequest::new(
+            "https://example.com/1".to_owned(),
+            ImageRequestBody::SingleImage { image: image("1.png") },
+        );
+        assert_eq!(request.text(), "https://example.com/1");
+
+        request.tags = vec!["sky".to_owned()];
+        assert_eq!(request.text(), "#sky\n\nhttps://example.com/1");
+    }
+
+    #[test]
+    fn text_of_an_upload_has_no_source() {
+        let mut request = ImageRequest::new(
+            String::new(),
+            ImageRequestBody::SingleImage { image: image("1.png") },
+        );
+        assert_eq!(request.text(), "");
+
+        request.tags = vec!["sky".to_owned()];
+        assert_eq!(request.text(), "#sky");
+    }
+
+    #[test]
+    fn body_depends_on_the_number_of_images() {
+        assert!(ImageRequestBody::from_images(Vec::new()).is_none());
+
+        match ImageRequestBody::from_images(vec![image("1.png")]) {
+            Some(ImageRequestBody::SingleImage { image }) => assert_eq!(image.filename, "1.png"),
+            _ => panic!("one image should be a single image"),
+        }
+
+        match ImageRequestBody::from_images(vec![image("1.png"), image("2.png")]) {
+            Some(ImageRequestBody::Album { images }) => assert_eq!(
+                images.iter().map(|image| image.filename.as_str()).collect::<Vec<_>>(),
+                vec!["1.png", "2.png"]
+            ),
+            _ => panic!("two images should be an album"),
+        }
+    }
 }
@@ -0,0 +1,551 @@
+use crate::config::{ScraperDefinition, ScraperFixture, ScraperFormat};
+use crate::request::{Image, ImageRequest, ImageRequestBody};
+use crate::source::{FetchResult, LinkPattern, Source};
+use crate::utils::ResultExtension;
+use act_zero::{Actor, ActorResult, Produces};
+use futures::future::join_all;
+use regex::Regex;
+use reqwest::Url;
+use scraper::{Html, Selector};
+use serde_json::Value;
+use serde_json_path::JsonPath;
+use std::fmt;
+
+/// A response parsed according to the format of its definition.
+enum Document {
+    Json(Value),
+    Html(Html),
+}
+
+impl Document {
+    fn parse(format: ScraperFormat, body: &str) -> anyhow::Result<Self> {
+        Ok(match format {
+            ScraperFormat::Json => Document::Json(serde_json::from_str(body)?),
+            ScraperFormat::Html => Document::Html(Html::parse_document(body)),
+        })
+    }
+}
+
+/// Expression for one field of a post.
+enum Query {
+    Json(JsonPath),
+    /// Selector and the attribute to read, the text when `None`.
+    Html(Selector, Option<String>),
+}
+
+impl Query {
+    fn parse(format: ScraperFormat, expression: &str) -> anyhow::Result<Self> {
+        match format {
+            ScraperFormat::Json => JsonPath::parse(expression)
+                .map(Query::Json)
+                .map_err(|e| anyhow::anyhow!("Invalid JSONPath {}: {}", expression, e)),
+            ScraperFormat::Html => {
+                let attribute = Regex::new(r"^(.*)@([\w-]+)$").unwrap();
+                let (selector, attribute) = match attribute.captures(expression) {
+                    Some(captures) => (
+                        captures.get(1).unwrap().as_str(),
+                        Some(captures[2].to_owned()),
+                    ),
+                    None => (expression, None),
+                };
+                Selector::parse(selector)
+                    .map(|selector| Query::Html(selector, attribute))
+                    .map_err(|e| anyhow::anyhow!("Invalid selector {}: {}", selector, e))
+            }
+        }
+    }
+
+    /// Every non-empty value the expression yields, in document order.
+    fn values(&self, document: &Document) -> Vec<String> {
+        let values = match (self, document) {
+            (Query::Json(path), Document::Json(value)) => path
+                .query(value)
+                .all()
+                .into_iter()
+                .flat_map(json_strings)
+                .collect(),
+            (Query::Html(selector, attribute), Document::Html(html)) => html
+                .select(selector)
+                .filter_map(|element| match attribute {
+                    Some(attribute) => element.attr(attribute).map(str::to_owned),
+                    None => Some(element.text().collect()),
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        values
+            .into_iter()
+            .map(|value: String| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+            .collect()
+    }
+}
+
+/// Scalars as text, arrays flattened.
+fn json_strings(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(text) => vec![text.clone()],
+        Value::Number(number) => vec![number.to_string()],
+        Value::Bool(flag) => vec![flag.to_string()],
+        Value::Array(items) => items.iter().flat_map(json_strings).collect(),
+        Value::Null | Value::Object(_) => Vec::new(),
+    }
+}
+
+/// Fields of a post found in a response.
+pub struct Post {
+    pub images: Vec<Url>,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// A compiled [`ScraperDefinition`].
+pub struct Scraper {
+    name: String,
+    pattern: Regex,
+    canonical: String,
+    fetch_url: String,
+    format: ScraperFormat,
+    images: Query,
+    title: Option<Query>,
+    author: Option<Query>,
+    tags: Option<Query>,
+}
+
+impl Scraper {
+    pub fn new(definition: &ScraperDefinition) -> anyhow::Result<Self> {
+        let format = definition.format;
+        let optional = |expression: &Option<String>| {
+            expression
+                .as_deref()
+                .map(|expression| Query::parse(format, expression))
+                .transpose()
+        };
+
+        Ok(Self {
+            name: definition.name.clone(),
+            pattern: Regex::new(&definition.url_pattern)?,
+            canonical: definition.canonical.clone().unwrap_or_else(|| "$0".to_owned()),
+            fetch_url: definition.fetch_url.clone(),
+            format,
+            images: Query::parse(format, &definition.images)?,
+            title: optional(&definition.title)?,
+            author: optional(&definition.author)?,
+            tags: optional(&definition.tags)?,
+        })
+    }
+
+    /// Canonical link for the first link of the site in `text`.
+    fn canonical(&self, text: &str) -> Option<String> {
+        let captures = self.pattern.captures(text)?;
+        let mut url = String::new();
+        captures.expand(&self.canonical, &mut url);
+        Some(url)
+    }
+
+    /// What to download for a canonical link.
+    fn fetch_url(&self, url: &str) -> anyhow::Result<Url> {
+        let captures = self
+            .pattern
+            .captures(url)
+            .ok_or_else(|| anyhow::anyhow!("{} does not match the {} scraper", url, self.name))?;
+        let mut fetch_url = String::new();
+        captures.expand(&self.fetch_url, &mut fetch_url);
+        Ok(Url::parse(&fetch_url)?)
+    }
+
+    /// Reads a post out of a response of `fetch_url`, image links are resolved against it.
+    pub fn extract(&self, body: &str, fetch_url: &Url) -> anyhow::Result<Post> {
+        let document = Document::parse(self.format, body)?;
+        let first = |query: &Option<Query>| {
+            query
+                .as_ref()
+                .and_then(|query| query.values(&document).into_iter().next())
+        };
+
+        let images = self
+            .images
+            .values(&document)
+            .into_iter()
+            .filter_map(|image| {
+                fetch_url
+                    .join(&image)
+                    .on_error(|e| log::warn!("Skip image {} of {}: {}", image, self.name, e))
+                    .ok()
+            })
+            .collect();
+
+        Ok(Post {
+            images,
+            title: first(&self.title),
+            author: first(&self.author),
+            tags: self
+                .tags
+                .as_ref()
+                .map(|tags| tags.values(&document))
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// Receives posts from a site described by a [`ScraperDefinition`].
+pub struct ScraperReceiveActor {
+    client: reqwest::Client,
+    scraper: Scraper,
+    url_pattern: String,
+}
+
+impl Actor for ScraperReceiveActor {}
+
+impl ScraperReceiveActor {
+    pub fn new(definition: &ScraperDefinition) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("heroku_bot/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+
+        Ok(Self {
+            client,
+            scraper: Scraper::new(definition)?,
+            url_pattern: definition.url_pattern.clone(),
+        })
+    }
+
+    async fn download(&self, url: &Url) -> anyhow::Result<Image> {
+        let data = self
+            .client
+            .get(url.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+            .on_error(|_| log::error!("Error on download image"))?;
+
+        let filename = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .unwrap_or_default()
+            .to_owned();
+        let video = [".mp4", ".webm", ".mov"]
+            .iter()
+            .any(|extension| filename.to_lowercase().ends_with(extension));
+        Ok(if video {
+            Image::video(filename, data.as_ref().into())
+        } else {
+            Image::new(filename, data.as_ref().into())
+        })
+    }
+
+    async fn receive(&mut self, url: String) -> FetchResult {
+        log::info!("Start {} process {}", self.scraper.name, url);
+
+        let fetch_url = self.scraper.fetch_url(&url)?;
+        let body = self
+            .client
+            .get(fetch_url.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await
+            .on_error(|_| log::error!("Error on request {}", fetch_url))?;
+        let post = self.scraper.extract(&body, &fetch_url)?;
+
+        let images: Vec<_> = join_all(post.images.iter().map(|image| self.download(image)))
+            .await
+            .into_iter()
+            .filter_map(|r| r.on_error(|e| log::error!("{}", e)).ok())
+            .collect();
+
+        log::info!("Downloaded {} images", images.len());
+
+        let body = ImageRequestBody::from_images(images)
+            .ok_or_else(|| anyhow::anyhow!("{} has no images", url))?;
+        let mut request = ImageRequest::new(url, body);
+        let caption = vec![post.title, post.author]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        request.caption = Some(caption).filter(|caption| !caption.is_empty());
+        request.tags = post.tags;
+        Ok(request)
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for ScraperReceiveActor {
+    fn name(&self) -> &str {
+        &self.scraper.name
+    }
+
+    fn link_patterns(&self) -> Vec<LinkPattern> {
+        vec![LinkPattern::new(&self.url_pattern, &self.scraper.canonical)]
+    }
+
+    async fn fetch(&mut self, url: String) -> ActorResult<FetchResult> {
+        Produces::ok(self.receive(url).await)
+    }
+}
+
+/// Outcome of checking a definition against one of its fixtures.
+pub enum CheckResult {
+    Passed { scraper: String, url: String, post: Post },
+    Failed { scraper: String, url: Option<String>, error: anyhow::Error },
+    NoFixtures { scraper: String },
+}
+
+impl CheckResult {
+    pub fn is_failed(&self) -> bool {
+        matches!(self, CheckResult::Failed { .. })
+    }
+}
+
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckResult::Passed { scraper, url, post } => write!(
+                f,
+                "ok   {} {}: images {:?}, title {:?}, author {:?}, tags {:?}",
+                scraper,
+                url,
+                post.images.iter().map(Url::as_str).collect::<Vec<_>>(),
+                post.title,
+                post.author,
+                post.tags
+            ),
+            CheckResult::Failed { scraper, url: Some(url), error } => {
+                write!(f, "FAIL {} {}: {:#}", scraper, url, error)
+            }
+            CheckResult::Failed { scraper, url: None, error } => {
+                write!(f, "FAIL {}: {:#}", scraper, error)
+            }
+            CheckResult::NoFixtures { scraper } => write!(f, "WARN {}: no fixtures", scraper),
+        }
+    }
+}
+
+/// Runs every definition against its fixtures without network access.
+pub fn check(definitions: &[ScraperDefinition]) -> Vec<CheckResult> {
+    let mut results = Vec::new();
+
+    for definition in definitions {
+        let scraper = match Scraper::new(definition) {
+            Ok(scraper) => scraper,
+            Err(error) => {
+                results.push(CheckResult::Failed {
+                    scraper: definition.name.clone(),
+                    url: None,
+                    error,
+                });
+                continue;
+            }
+        };
+        if definition.fixtures.is_empty() {
+            results.push(CheckResult::NoFixtures {
+                scraper: definition.name.clone(),
+            });
+        }
+
+        for fixture in &definition.fixtures {
+            let scraper_name = definition.name.clone();
+            let url = fixture.url.clone();
+            results.push(match check_fixture(&scraper, fixture) {
+                Ok(post) => CheckResult::Passed {
+                    scraper: scraper_name,
+                    url,
+                    post,
+                },
+                Err(error) => CheckResult::Failed {
+                    scraper: scraper_name,
+                    url: Some(url),
+                    error,
+                },
+            });
+        }
+    }
+
+    results
+}
+
+fn check_fixture(scraper: &Scraper, fixture: &ScraperFixture) -> anyhow::Result<Post> {
+    let url = scraper
+        .canonical(&fixture.url)
+        .ok_or_else(|| anyhow::anyhow!("url_pattern does not match"))?;
+    let fetch_url = scraper
+        .fetch_url(&url)
+        .map_err(|e| e.context(format!("canonical link {} is not usable", url)))?;
+    let body = std::fs::read_to_string(&fixture.file)
+        .map_err(|e| anyhow::anyhow!("Error on read {}: {}", fixture.file, e))?;
+    let post = scraper.extract(&body, &fetch_url)?;
+
+    if post.images.is_empty() {
+        anyhow::bail!("no images found");
+    }
+    if let Some(images) = fixture.images {
+        anyhow::ensure!(
+            post.images.len() == images,
+            "{} images found, {} expected",
+            post.images.len(),
+            images
+        );
+    }
+    let expect = |field: &str, found: &Option<String>, expected: &Option<String>| {
+        match expected {
+            Some(expected) if found.as_ref() != Some(expected) => Err(anyhow::anyhow!(
+                "{} is {:?}, {:?} expected",
+                field,
+                found,
+                expected
+            )),
+            _ => Ok(()),
+        }
+    };
+    expect("title", &post.title, &fixture.title)?;
+    expect("author", &post.author, &fixture.author)?;
+    if let Some(tags) = &fixture.tags {
+        anyhow::ensure!(
+            &post.tags == tags,
+            "tags are {:?}, {:?} expected",
+            post.tags,
+            tags
+        );
+    }
+
+    Ok(post)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn definition(format: &str, fixture: &str, queries: Value) -> ScraperDefinition {
+        let mut definition = json!({
+            "name": "example",
+            "url_pattern": r"https?://(?:www\.)?example\.com/posts/(?P<id>\d+)",
+            "canonical": "https://example.com/posts/$id",
+            "fetch_url": "https://example.com/api/posts/$id",
+            "format": format,
+            "fixtures": [{
+                "url": "https://www.example.com/posts/1234?ref=feed",
+                "file": fixture,
+                "images": 2,
+                "title": "Sunset over the bay",
+                "author": "painter",
+                "tags": ["landscape", "sea"],
+            }],
+        });
+        definition.as_object_mut().unwrap().extend(queries.as_object().unwrap().clone());
+        serde_json::from_value(definition).unwrap()
+    }
+
+    fn json_definition() -> ScraperDefinition {
+        definition(
+            "json",
+            "fixtures/scrapers/post.json",
+            json!({
+                "images": "$.post.files[*].url",
+                "title": "$.post.title",
+                "author": "$.post.user.name",
+                "tags": "$.post.tags",
+            }),
+        )
+    }
+
+    fn html_definition() -> ScraperDefinition {
+        definition(
+            "html",
+            "fixtures/scrapers/post.html",
+            json!({
+                "images": "img.full@src",
+                "title": "h1.title",
+                "author": ".author",
+                "tags": ".tags a",
+            }),
+        )
+    }
+
+    fn fetch_url() -> Url {
+        Url::parse("https://example.com/api/posts/1234").unwrap()
+    }
+
+    fn assert_post(post: &Post) {
+        assert_eq!(
+            post.images.iter().map(Url::as_str).collect::<Vec<_>>(),
+            vec![
+                "https://example.com/media/1234/first.png",
+                "https://cdn.example.com/1234/second.jpg",
+            ]
+        );
+        assert_eq!(post.title.as_deref(), Some("Sunset over the bay"));
+        assert_eq!(post.author.as_deref(), Some("painter"));
+        assert_eq!(post.tags, vec!["landscape", "sea"]);
+    }
+
+    #[test]
+    fn extracts_json() {
+        let scraper = Scraper::new(&json_definition()).unwrap();
+        let post = scraper
+            .extract(include_str!("../fixtures/scrapers/post.json"), &fetch_url())
+            .unwrap();
+        assert_post(&post);
+    }
+
+    #[test]
+    fn extracts_html() {
+        let scraper = Scraper::new(&html_definition()).unwrap();
+        let post = scraper
+            .extract(include_str!("../fixtures/scrapers/post.html"), &fetch_url())
+            .unwrap();
+        assert_post(&post);
+    }
+
+    #[test]
+    fn rejects_malformed_json() {
+        let scraper = Scraper::new(&json_definition()).unwrap();
+        assert!(scraper
+            .extract(include_str!("../fixtures/scrapers/post.html"), &fetch_url())
+            .is_err());
+    }
+
+    #[test]
+    fn builds_links_from_the_pattern() {
+        let scraper = Scraper::new(&json_definition()).unwrap();
+        let url = scraper
+            .canonical("look at https://www.example.com/posts/1234?ref=feed")
+            .unwrap();
+        assert_eq!(url, "https://example.com/posts/1234");
+        assert_eq!(scraper.fetch_url(&url).unwrap(), fetch_url());
+    }
+
+    #[test]
+    fn checks_fixtures() {
+        let results = check(&[json_definition(), html_definition()]);
+        assert_eq!(results.len(), 2);
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, CheckResult::Passed { .. })));
+    }
+
+    #[test]
+    fn reports_mismatches() {
+        let mut definition = html_definition();
+        definition.fixtures[0].title = Some("Sunrise".to_owned());
+        definition.fixtures.push(ScraperFixture {
+            file: "fixtures/scrapers/missing.html".to_owned(),
+            ..definition.fixtures[0].clone()
+        });
+        let mut invalid = json_definition();
+        invalid.images = "$.post[".to_owned();
+        let mut empty = json_definition();
+        empty.fixtures.clear();
+
+        let results = check(&[definition, invalid, empty]);
+        assert_eq!(results.len(), 4);
+        assert!(results[..3].iter().all(CheckResult::is_failed));
+        assert!(matches!(results[3], CheckResult::NoFixtures { .. }));
+    }
+}
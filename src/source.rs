@@ -0,0 +1,346 @@
+use crate::request::ImageRequest;
+use act_zero::runtimes::tokio::spawn_actor;
+use act_zero::{call, upcast, Actor, ActorResult, Addr};
+use regex::Regex;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::ops::Range;
+
+pub type FetchResult = anyhow::Result<ImageRequest>;
+
+/// A site images can be pulled from.
+#[async_trait::async_trait]
+pub trait Source: Actor + Send {
+    fn name(&self) -> &str;
+
+    /// Links this source can fetch. Called once, when the source is registered.
+    fn link_patterns(&self) -> Vec<LinkPattern>;
+
+    /// Fetches a link previously produced by one of the [`link_patterns`](Source::link_patterns).
+    ///
+    /// Fails with [`Unsupported`] when the link turns out not to be one of this source, the link
+    /// is then fetched by the next source that recognizes it.
+    async fn fetch(&mut self, url: String) -> ActorResult<FetchResult>;
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct Unsupported(pub String);
+
+type LinkFilter = Box<dyn Fn(&str) -> bool + Send + Sync>;
+
+pub struct LinkPattern {
+    regex: Regex,
+    canonical: String,
+    filter: Option<LinkFilter>,
+}
+
+impl LinkPattern {
+    /// `canonical` is expanded with the captures of `pattern` to build the link passed to
+    /// [`Source::fetch`], see [`regex::Captures::expand`].
+    pub fn new(pattern: &str, canonical: &str) -> Self {
+        Self {
+            regex: Regex::new(pattern).expect("Error on compile regex"),
+            canonical: canonical.to_owned(),
+            filter: None,
+        }
+    }
+
+    /// Leaves links whose canonical url `filter` rejects to the sources registered later.
+    pub fn with_filter<F: Fn(&str) -> bool + Send + Sync + 'static>(mut self, filter: F) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Canonical links of the matches in `text` the filter accepts, with where they were found.
+    fn find<'a>(&'a self, text: &'a str) -> impl Iterator<Item = (Range<usize>, String)> + 'a {
+        self.regex.captures_iter(text).filter_map(move |captures| {
+            let whole = captures.get(0).expect("Match always has group 0");
+            let mut url = String::new();
+            captures.expand(&self.canonical, &mut url);
+            if self.filter.as_ref().is_some_and(|filter| !filter(&url)) {
+                return None;
+            }
+            Some((whole.range(), url))
+        })
+    }
+}
+
+/// Link claimed by a source.
+struct Claim<K> {
+    range: Range<usize>,
+    source: K,
+    url: String,
+    /// Links later sources found at the same place, at most one per source.
+    fallbacks: Vec<(K, String)>,
+}
+
+/// Links in `text` claimed by the patterns of each source, in order of appearance.
+///
+/// Sources listed first take precedence when their links overlap, duplicates are dropped.
+fn claim_links<'a, K: Copy + Eq + Hash>(
+    sources: impl IntoIterator<Item = (K, &'a [LinkPattern])>,
+    text: &str,
+) -> Vec<Claim<K>> {
+    let mut claimed: Vec<Claim<K>> = Vec::new();
+
+    for (source, patterns) in sources {
+        for pattern in patterns {
+            for (range, url) in pattern.find(text) {
+                let overlapped = claimed
+                    .iter_mut()
+                    .find(|claim| range.start < claim.range.end && claim.range.start < range.end);
+                match overlapped {
+                    None => claimed.push(Claim {
+                        range,
+                        source,
+                        url,
+                        fallbacks: Vec::new(),
+                    }),
+                    Some(claim) => {
+                        let known = claim.source == source
+                            || claim.fallbacks.iter().any(|(other, _)| *other == source);
+                        if !known {
+                            claim.fallbacks.push((source, url));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    claimed.sort_by_key(|claim| claim.range.start);
+
+    let mut seen = HashSet::new();
+    claimed
+        .into_iter()
+        .filter(|claim| seen.insert((claim.source, claim.url.clone())))
+        .collect()
+}
+
+/// Links of `text` the source would be asked to fetch if it were the only one.
+#[cfg(test)]
+pub fn canonical_links<S: Source>(source: &S, text: &str) -> Vec<String> {
+    let patterns = source.link_patterns();
+    claim_links(Some(((), patterns.as_slice())), text)
+        .into_iter()
+        .map(|claim| claim.url)
+        .collect()
+}
+
+/// Escaped host of `base_url` without `www.`, for sources configured by base url.
+pub fn host_pattern(base_url: &str) -> String {
+    reqwest::Url::parse(base_url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| regex::escape(host.trim_start_matches("www."))))
+        .unwrap_or_else(|| panic!("{} has no host", base_url))
+}
+
+struct RegisteredSource {
+    name: String,
+    patterns: Vec<LinkPattern>,
+    addr: Addr<dyn Source>,
+}
+
+#[derive(Clone)]
+pub struct Link {
+    pub source: String,
+    pub url: String,
+    addr: Addr<dyn Source>,
+    /// Links of other sources found at the same place, tried in order when the source turns
+    /// out not to support the link.
+    fallbacks: Vec<Link>,
+}
+
+impl Link {
+    /// Link to a source that is not running, fetching it fails.
+    #[cfg(test)]
+    pub fn detached(source: &str, url: &str) -> Self {
+        Self {
+            source: source.to_owned(),
+            url: url.to_owned(),
+            addr: Addr::detached(),
+            fallbacks: Vec::new(),
+        }
+    }
+
+    pub async fn fetch(&self) -> FetchResult {
+        let mut result = self.fetch_from_source().await;
+        for fallback in &self.fallbacks {
+            match &result {
+                Err(e) if e.is::<Unsupported>() => {
+                    log::info!("{}, try {} source: {}", e, fallback.source, fallback.url);
+                    result = fallback.fetch_from_source().await;
+                }
+                _ => break,
+            }
+        }
+        result
+    }
+
+    async fn fetch_from_source(&self) -> FetchResult {
+        call!(self.addr.fetch(self.url.clone()))
+            .await
+            .map_err(|_| anyhow::anyhow!("{} source is not available", self.source))?
+    }
+}
+
+#[derive(Default)]
+pub struct SourceRegistry {
+    sources: Vec<RegisteredSource>,
+}
+
+impl SourceRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<S: Source + 'static>(&mut self, source: S) {
+        let name = source.name().to_owned();
+        let patterns = source.link_patterns();
+        log::info!("Register {} source", name);
+
+        self.sources.push(RegisteredSource {
+            name,
+            patterns,
+            addr: upcast!(spawn_actor(source)),
+        });
+    }
+
+    /// Finds every link in `text` claimed by a registered source, in order of appearance.
+    ///
+    /// Sources registered first take precedence when their links overlap, duplicates are dropped.
+    pub fn find_links(&self, text: &str) -> Vec<Link> {
+        let patterns = self
+            .sources
+            .iter()
+            .enumerate()
+            .map(|(index, source)| (index, source.patterns.as_slice()));
+
+        let link = |index: usize, url: String, fallbacks: Vec<Link>| {
+            let source = &self.sources[index];
+            Link {
+                source: source.name.clone(),
+                url,
+                addr: source.addr.clone(),
+                fallbacks,
+            }
+        };
+
+        claim_links(patterns, text)
+            .into_iter()
+            .map(|claim| {
+                let fallbacks = claim
+                    .fallbacks
+                    .into_iter()
+                    .map(|(index, url)| link(index, url, Vec::new()))
+                    .collect();
+                link(claim.source, claim.url, fallbacks)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn links(sources: &[(&'static str, Vec<LinkPattern>)], text: &str) -> Vec<(&'static str, String)> {
+        claim_links(
+            sources
+                .iter()
+                .map(|(name, patterns)| (*name, patterns.as_slice())),
+            text,
+        )
+        .into_iter()
+        .map(|claim| (claim.source, claim.url))
+        .collect()
+    }
+
+    #[test]
+    fn finds_links_in_order_of_appearance() {
+        let sources = [
+            ("first", vec![LinkPattern::new(r"https://a\.test/(?P<id>\d+)", "a:$id")]),
+            ("second", vec![LinkPattern::new(r"https://b\.test/(?P<id>\d+)", "b:$id")]),
+        ];
+        assert_eq!(
+            links(&sources, "https://b.test/1 and https://a.test/2, https://b.test/3"),
+            vec![
+                ("second", "b:1".to_owned()),
+                ("first", "a:2".to_owned()),
+                ("second", "b:3".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn first_source_wins_overlapping_links() {
+        let sources = [
+            ("specific", vec![LinkPattern::new(r"https://a\.test/posts/(?P<id>\d+)", "post:$id")]),
+            ("any", vec![LinkPattern::new(r"https://\S+", "$0")]),
+        ];
+        assert_eq!(
+            links(&sources, "https://a.test/posts/1 https://a.test/about"),
+            vec![
+                ("specific", "post:1".to_owned()),
+                ("any", "https://a.test/about".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_duplicates() {
+        let sources = [(
+            "only",
+            vec![LinkPattern::new(r"https?://(?:www\.)?a\.test/(?P<id>\d+)", "a:$id")],
+        )];
+        assert_eq!(
+            links(&sources, "https://a.test/1 http://www.a.test/1 https://a.test/2"),
+            vec![("only", "a:1".to_owned()), ("only", "a:2".to_owned())]
+        );
+    }
+
+    #[test]
+    fn filtered_links_fall_through() {
+        let sources = [
+            (
+                "picky",
+                vec![LinkPattern::new(r"https://\S+", "$0").with_filter(|url| url.ends_with(".png"))],
+            ),
+            ("any", vec![LinkPattern::new(r"https://\S+", "$0")]),
+        ];
+        assert_eq!(
+            links(&sources, "https://a.test/1.png https://a.test/page"),
+            vec![
+                ("picky", "https://a.test/1.png".to_owned()),
+                ("any", "https://a.test/page".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_overlapping_links_of_later_sources_as_fallbacks() {
+        let sources = [
+            ("specific", vec![LinkPattern::new(r"https://a\.test/posts/(?P<id>\d+)", "post:$id")]),
+            ("other", vec![LinkPattern::new(r"https://a\.test/\S+", "other:$0")]),
+            ("any", vec![LinkPattern::new(r"https://\S+", "$0")]),
+        ];
+        let claims = claim_links(
+            sources
+                .iter()
+                .map(|(name, patterns)| (*name, patterns.as_slice())),
+            "https://a.test/posts/1 https://b.test/about",
+        );
+
+        assert_eq!(claims.len(), 2);
+        assert_eq!((claims[0].source, claims[0].url.as_str()), ("specific", "post:1"));
+        assert_eq!(
+            claims[0].fallbacks,
+            vec![
+                ("other", "other:https://a.test/posts/1".to_owned()),
+                ("any", "https://a.test/posts/1".to_owned()),
+            ]
+        );
+        assert!(claims[1].fallbacks.is_empty());
+    }
+}
@@ -1,5 +1,5 @@
 use crate::config::Config;
-use crate::request::{Image, ImageRequest, ImageRequestBody, ImageSender};
+use crate::request::{DeliveryResult, Image, ImageRequest, ImageRequestBody, ImageSender};
 use crate::utils::ResultExtension;
 use act_zero::{Actor, ActorError, ActorResult, Addr, Produces};
 use std::sync::Arc;
@@ -7,6 +7,7 @@ use tap::Pipe;
 use teloxide_core::adaptors::Throttle;
 use teloxide_core::prelude::{Request, Requester};
 use teloxide_core::prelude::RequesterExt;
+use teloxide_core::payloads::{SendAnimationSetters, SendPhotoSetters, SendVideoSetters};
 
 pub struct TelegramSenderActor {
     bot: Throttle<teloxide_core::Bot>,
@@ -31,28 +32,16 @@ impl Actor for TelegramSenderActor {
 
 impl TelegramSenderActor {
     pub fn new(config: Arc<Config>) -> Self {
-        let client = reqwest::Client::builder()
-            .build()
-            .expect("Error on build client");
-
-        let bot = teloxide_core::Bot::with_client(&config.telegram_token, client)
-            .pipe(|bot| match config.telegram_host.as_ref() {
-                None => bot,
-                Some(host) => bot.set_api_url(reqwest::Url::parse(host).expect("WTF"))
-            })
-            .throttle(Default::default());
-
+        let bot = build_bot(&config).throttle(Default::default());
 
         Self { config, bot }
     }
 
-    async fn upload_images(&self, album: &[Image], request: &str) -> ActorResult<()> {
+    async fn upload_images(&self, album: &[Image], request: &str, spoiler: bool) -> ActorResult<()> {
         let media: Vec<_> = album
             .iter()
-            .map(image_as_teloxide_file)
-            .map(|file| teloxide_core::types::InputMediaPhoto::new(file))
-            .map(|photo| teloxide_core::types::InputMedia::Photo(photo))
-            .collect();
+            .map(|image| image_as_teloxide_media(image, spoiler))
+            .collect::<anyhow::Result<_>>()?;
 
         self.bot
             .send_media_group(self.config.telegram_target, media)
@@ -67,73 +56,168 @@ impl TelegramSenderActor {
 
 #[async_trait::async_trait]
 impl ImageSender for TelegramSenderActor {
-    async fn handle_request(&mut self, request: Arc<ImageRequest>) -> ActorResult<()> {
+    async fn handle_request(&mut self, request: Arc<ImageRequest>) -> ActorResult<DeliveryResult> {
         log::info!("Handle request from {}", request.source);
-
-        let _ = self
-            .bot
-            .send_message(self.config.telegram_target, &request.source)
-            .send()
-            .await
-            .on_error(|_| log::error!("Error on send message"));
+        let mut errors = Vec::new();
+
+        // Telegram refuses empty messages, uploads may come without any text
+        let text = request.text();
+        if !text.trim().is_empty() {
+            if let Err(e) = self
+                .bot
+                .send_message(self.config.telegram_target, text)
+                .send()
+                .await
+                .on_error(|_| log::error!("Error on send message"))
+            {
+                errors.push(format!("send message: {}", e));
+            }
+        }
 
         match &request.body {
             ImageRequestBody::SingleImage { image } => {
                 let file = image_as_teloxide_doc_file(image);
-                let image_file = image_as_teloxide_file(image);
 
-                let _ = self
-                    .bot
-                    .send_photo(self.config.telegram_target, image_file)
-                    .send()
-                    .await
-                    .on_error(|_| log::error!("Error on upload as image"));
+                let sent = if image.is_animation() {
+                    let mut animation = self
+                        .bot
+                        .send_animation(self.config.telegram_target, image_as_teloxide_animation_file(image))
+                        .has_spoiler(request.nsfw);
+                    if let Some(caption) = media_caption(image) {
+                        animation = animation.caption(caption);
+                    }
+                    animation.send().await.map_err(Into::into)
+                } else if image.is_video() {
+                    let mut video = self
+                        .bot
+                        .send_video(self.config.telegram_target, image_as_teloxide_doc_file(image))
+                        .has_spoiler(request.nsfw);
+                    if let Some(caption) = media_caption(image) {
+                        video = video.caption(caption);
+                    }
+                    video.send().await.map_err(Into::into)
+                } else {
+                    match image_as_teloxide_file(image) {
+                        Ok(file) => {
+                            let mut photo = self
+                                .bot
+                                .send_photo(self.config.telegram_target, file)
+                                .has_spoiler(request.nsfw);
+                            if let Some(caption) = media_caption(image) {
+                                photo = photo.caption(caption);
+                            }
+                            photo.send().await.map_err(Into::into)
+                        }
+                        Err(e) => Err(e),
+                    }
+                };
+                if let Err(e) = sent.on_error(|_| log::error!("Error on upload as image")) {
+                    errors.push(format!("upload as image: {}", e));
+                }
 
-                let _ = self
+                if let Err(e) = self
                     .bot
                     .send_document(self.config.telegram_target, file)
                     .send()
                     .await
-                    .on_error(|_| log::error!("Error on upload as document"));
+                    .on_error(|_| log::error!("Error on upload as document"))
+                {
+                    errors.push(format!("upload as document: {}", e));
+                }
 
                 log::info!("Sent one image from {}", request.source);
             }
             ImageRequestBody::Album { images } => {
                 for album in images.chunks(10) {
-                    let _ = self
-                        .upload_images(album, request.source.as_str())
+                    if let Err(e) = self
+                        .upload_images(album, request.source.as_str(), request.nsfw)
                         .await
-                        .on_error(|_| log::error!("Error on upload as image"));
+                        .on_error(|_| log::error!("Error on upload as image"))
+                    {
+                        errors.push(format!("upload as images: {}", e));
+                    }
 
                     for image in album {
                         let file = image_as_teloxide_doc_file(image);
 
-                        let _ = self
+                        if let Err(e) = self
                             .bot
                             .send_document(self.config.telegram_target, file)
                             .send()
                             .await
-                            .on_error(|_| log::error!("Error on upload as document"));
+                            .on_error(|_| log::error!("Error on upload as document"))
+                        {
+                            errors.push(format!("upload as document: {}", e));
+                        }
                     }
                 }
             }
         }
 
-        Produces::ok(())
+        if errors.is_empty() {
+            Produces::ok(Ok(()))
+        } else {
+            Produces::ok(Err(anyhow::anyhow!(errors.join("; "))))
+        }
     }
 }
 
-fn image_as_teloxide_file(image: &Image) -> teloxide_core::types::InputFile {
+/// Bot talking to the configured Bot API host.
+pub fn build_bot(config: &Config) -> teloxide_core::Bot {
+    let client = reqwest::Client::builder()
+        .build()
+        .expect("Error on build client");
+
+    teloxide_core::Bot::with_client(&config.telegram_token, client)
+        .pipe(|bot| match config.telegram_host.as_ref() {
+            None => bot,
+            Some(host) => bot.set_api_url(reqwest::Url::parse(host).expect("WTF"))
+        })
+}
+
+fn image_as_teloxide_file(image: &Image) -> anyhow::Result<teloxide_core::types::InputFile> {
     let file_name = image.filename.clone();
-    let image = image::load_from_memory(image.data.as_ref()).expect("Error on load image");
+    let image = image::load_from_memory(image.data.as_ref())
+        .map_err(|e| anyhow::anyhow!("Error on load image {}: {}", file_name, e))?;
     let mut buffer = Vec::new();
     let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, 90);
-    encoder.encode_image(&image).expect("Error on encode image");
+    encoder.encode_image(&image)?;
 
-    teloxide_core::types::InputFile::memory(
+    Ok(teloxide_core::types::InputFile::memory(
         buffer
     )
-        .file_name(file_name)
+        .file_name(file_name))
+}
+
+/// Photo re-encoded as JPEG, or the video as it is.
+/// Albums cannot hold animations, they go as videos when there is an MP4 of them.
+fn image_as_teloxide_media(image: &Image, spoiler: bool) -> anyhow::Result<teloxide_core::types::InputMedia> {
+    use teloxide_core::types::{InputMedia, InputMediaPhoto, InputMediaVideo};
+
+    if image.is_video() || (image.is_animation() && image.mp4.is_some()) {
+        let file = if image.is_video() {
+            image_as_teloxide_doc_file(image)
+        } else {
+            image_as_teloxide_animation_file(image)
+        };
+        let mut video = InputMediaVideo::new(file);
+        video.has_spoiler = spoiler;
+        video.caption = media_caption(image);
+        Ok(InputMedia::Video(video))
+    } else {
+        let mut photo = InputMediaPhoto::new(image_as_teloxide_file(image)?);
+        photo.has_spoiler = spoiler;
+        photo.caption = media_caption(image);
+        Ok(InputMedia::Photo(photo))
+    }
+}
+
+/// Alt text of the image, cut to the length Telegram accepts for captions.
+fn media_caption(image: &Image) -> Option<String> {
+    image
+        .alt
+        .as_ref()
+        .map(|alt| alt.chars().take(1024).collect())
 }
 
 fn image_as_teloxide_doc_file(image: &Image) -> teloxide_core::types::InputFile {
@@ -142,3 +226,14 @@ fn image_as_teloxide_doc_file(image: &Image) -> teloxide_core::types::InputFile
     )
         .file_name(image.filename.clone())
 }
+
+/// The MP4 of an animation when there is one, the GIF otherwise.
+fn image_as_teloxide_animation_file(image: &Image) -> teloxide_core::types::InputFile {
+    match &image.mp4 {
+        Some(mp4) => {
+            let stem = image.filename.rsplit_once('.').map_or(image.filename.as_str(), |(stem, _)| stem);
+            teloxide_core::types::InputFile::memory(mp4.to_vec()).file_name(format!("{}.mp4", stem))
+        }
+        None => image_as_teloxide_doc_file(image),
+    }
+}
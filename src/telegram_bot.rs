@@ -0,0 +1,216 @@
+use crate::config::Config;
+use crate::jobs::{ingest_text, JobManagerActor};
+use crate::source::SourceRegistry;
+use crate::telegram::build_bot;
+use crate::utils::ResultExtension;
+use act_zero::{call, Actor, ActorError, ActorResult, Addr, AddrLike, Produces, WeakAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use teloxide_core::payloads::{GetUpdatesSetters, SendMessageSetters};
+use teloxide_core::prelude::{Request, Requester};
+use teloxide_core::types::{AllowedUpdate, ChatId, Message, MessageEntityKind, MessageId, UpdateKind};
+
+/// Long polling timeout, in seconds.
+const POLL_TIMEOUT: u32 = 30;
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Accepts links sent to the bot in private messages by allowed users and replies
+/// with the state of the created jobs.
+pub struct TelegramBotActor {
+    addr: WeakAddr<Self>,
+    bot: teloxide_core::Bot,
+    allowed_users: Vec<u64>,
+    sources: Arc<SourceRegistry>,
+    jobs: Addr<JobManagerActor>,
+    offset: i32,
+}
+
+#[async_trait::async_trait]
+impl Actor for TelegramBotActor {
+    async fn started(&mut self, addr: Addr<Self>) -> ActorResult<()>
+    where
+        Self: Sized,
+    {
+        log::info!("Start Telegram bot polling");
+        self.addr = addr.downgrade();
+        addr.send_fut_with(|addr| async move {
+            while call!(addr.poll()).await.is_ok() {}
+        });
+        Produces::ok(())
+    }
+
+    async fn error(&mut self, error: ActorError) -> bool {
+        log::error!("{}", error);
+        false
+    }
+}
+
+impl TelegramBotActor {
+    pub fn new(config: &Config, sources: Arc<SourceRegistry>, jobs: Addr<JobManagerActor>) -> Self {
+        Self {
+            addr: WeakAddr::detached(),
+            bot: build_bot(config),
+            allowed_users: config.telegram_allowed_users.clone(),
+            sources,
+            jobs,
+            offset: 0,
+        }
+    }
+
+    async fn poll(&mut self) -> ActorResult<()> {
+        let updates = self
+            .bot
+            .get_updates()
+            .offset(self.offset)
+            .timeout(POLL_TIMEOUT)
+            .allowed_updates(vec![AllowedUpdate::Message])
+            .send()
+            .await
+            .on_error(|e| log::error!("Error on get updates: {}", e));
+
+        let updates = match updates {
+            Ok(updates) => updates,
+            Err(_) => {
+                tokio::time::sleep(RETRY_DELAY).await;
+                return Produces::ok(());
+            }
+        };
+
+        if let Some(last) = updates.last() {
+            self.offset = last.id + 1;
+            self.confirm_updates().await;
+        }
+
+        for update in updates {
+            if let UpdateKind::Message(message) = update.kind {
+                self.handle_message(message);
+            }
+        }
+
+        Produces::ok(())
+    }
+
+    /// Telegram drops updates only once a later offset is requested, so the received ones are
+    /// confirmed before their links are queued and a restart does not queue them again.
+    async fn confirm_updates(&self) {
+        let _ = self
+            .bot
+            .get_updates()
+            .offset(self.offset)
+            .limit(1)
+            .timeout(0)
+            .allowed_updates(vec![AllowedUpdate::Message])
+            .send()
+            .await
+            .on_error(|e| log::error!("Error on confirm updates: {}", e));
+    }
+
+    fn handle_message(&self, message: Message) {
+        if !message.chat.is_private() {
+            return;
+        }
+
+        let user = match message.from() {
+            Some(user) => user.id.0,
+            None => return,
+        };
+        if !self.allowed_users.contains(&user) {
+            log::warn!("Ignore message from not allowed user {}", user);
+            return;
+        }
+
+        let text = message_text(&message);
+        let bot = self.bot.clone();
+        let sources = self.sources.clone();
+        let jobs = self.jobs.clone();
+        self.addr.send_fut(async move {
+            let reply = |text| reply(&bot, message.chat.id, message.id, text);
+            ingest_text(&sources, &jobs, &text, reply).await;
+        });
+    }
+}
+
+async fn reply(bot: &teloxide_core::Bot, chat: ChatId, message: MessageId, text: String) {
+    let _ = bot
+        .send_message(chat, text)
+        .reply_to_message_id(message)
+        .send()
+        .await
+        .on_error(|e| log::error!("Error on reply: {}", e));
+}
+
+/// Message text together with the targets of hidden text links.
+fn message_text(message: &Message) -> String {
+    let mut text = message
+        .text()
+        .or_else(|| message.caption())
+        .unwrap_or_default()
+        .to_owned();
+
+    let entities = message.entities().or_else(|| message.caption_entities());
+    for entity in entities.into_iter().flatten() {
+        if let MessageEntityKind::TextLink { url } = &entity.kind {
+            text.push('\n');
+            text.push_str(url.as_str());
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(content: serde_json::Value) -> Message {
+        let mut message = serde_json::json!({
+            "message_id": 1,
+            "date": 1700000000,
+            "chat": {"id": 1, "type": "private", "first_name": "User"},
+            "from": {"id": 1, "is_bot": false, "first_name": "User"},
+        });
+        message
+            .as_object_mut()
+            .unwrap()
+            .extend(content.as_object().unwrap().clone());
+        serde_json::from_value(message).expect("Error on parse message")
+    }
+
+    #[test]
+    fn text_includes_hidden_link_targets() {
+        let message = message(serde_json::json!({
+            "text": "look at this and https://example.com/2",
+            "entities": [
+                {"type": "text_link", "offset": 8, "length": 4, "url": "https://example.com/1"},
+                {"type": "url", "offset": 17, "length": 21},
+            ],
+        }));
+
+        assert_eq!(
+            message_text(&message),
+            "look at this and https://example.com/2\nhttps://example.com/1"
+        );
+    }
+
+    #[test]
+    fn text_of_media_is_its_caption() {
+        let message = message(serde_json::json!({
+            "photo": [{"file_id": "id", "file_unique_id": "unique", "width": 1, "height": 1}],
+            "caption": "source",
+            "caption_entities": [
+                {"type": "text_link", "offset": 0, "length": 6, "url": "https://example.com/1"},
+            ],
+        }));
+
+        assert_eq!(message_text(&message), "source\nhttps://example.com/1");
+    }
+
+    #[test]
+    fn text_of_media_without_caption_is_empty() {
+        let message = message(serde_json::json!({
+            "photo": [{"file_id": "id", "file_unique_id": "unique", "width": 1, "height": 1}],
+        }));
+
+        assert_eq!(message_text(&message), "");
+    }
+}
@@ -0,0 +1,240 @@
+use crate::config::Config;
+use crate::request::{Image, ImageRequest, ImageRequestBody};
+use crate::source::{FetchResult, LinkPattern, Source};
+use crate::utils::ResultExtension;
+use act_zero::{Actor, ActorResult, Produces};
+use futures::future::join_all;
+use serde::Deserialize;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+struct Envelope {
+    response: PostsResponse,
+}
+
+#[derive(Deserialize)]
+struct PostsResponse {
+    posts: Vec<Post>,
+}
+
+#[derive(Deserialize)]
+struct Post {
+    #[serde(default)]
+    summary: String,
+    /// Legacy photo posts.
+    #[serde(default)]
+    photos: Vec<Photo>,
+    /// NPF content blocks.
+    #[serde(default)]
+    content: Vec<Block>,
+    /// Content of the reblogged posts, before the content of this one.
+    #[serde(default)]
+    trail: Vec<TrailItem>,
+}
+
+#[derive(Deserialize)]
+struct Photo {
+    original_size: PhotoSize,
+    #[serde(default)]
+    alt_sizes: Vec<PhotoSize>,
+    caption: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PhotoSize {
+    url: String,
+    #[serde(default)]
+    width: u32,
+}
+
+#[derive(Deserialize)]
+struct TrailItem {
+    #[serde(default)]
+    content: Vec<Block>,
+}
+
+#[derive(Deserialize)]
+struct Block {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    media: Vec<PhotoSize>,
+    alt_text: Option<String>,
+}
+
+impl Post {
+    /// Largest variant of every image in reading order, with its alt text.
+    fn images(&self) -> Vec<(&str, Option<&str>)> {
+        let legacy = self.photos.iter().map(|photo| {
+            let largest = photo
+                .alt_sizes
+                .iter()
+                .chain(Some(&photo.original_size))
+                .max_by_key(|size| size.width)
+                .unwrap_or(&photo.original_size);
+            (largest.url.as_str(), photo.caption.as_deref())
+        });
+
+        let blocks = self
+            .trail
+            .iter()
+            .flat_map(|item| item.content.iter())
+            .chain(self.content.iter())
+            .filter(|block| block.kind == "image")
+            .filter_map(|block| {
+                let largest = block.media.iter().max_by_key(|size| size.width)?;
+                Some((largest.url.as_str(), block.alt_text.as_deref()))
+            });
+
+        legacy.chain(blocks).collect()
+    }
+}
+
+/// Receives posts from Tumblr through the v2 api.
+pub struct TumblrReceiveActor {
+    client: reqwest::Client,
+    api_url: String,
+    api_key: String,
+}
+
+impl Actor for TumblrReceiveActor {}
+
+impl TumblrReceiveActor {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_url: config.tumblr_api_url.trim_end_matches('/').to_owned(),
+            api_key: config
+                .tumblr_api_key
+                .clone()
+                .expect("Tumblr api key is required"),
+        }
+    }
+
+    async fn download(&self, url: &str, alt: Option<&str>) -> anyhow::Result<Image> {
+        let data = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+            .on_error(|_| log::error!("Error on download image"))?;
+
+        let path = url.split('?').next().unwrap();
+        let mut image = Image::new(
+            path.rsplit('/').next().unwrap().to_string(),
+            data.as_ref().into(),
+        );
+        image.alt = alt
+            .map(str::trim)
+            .filter(|alt| !alt.is_empty())
+            .map(str::to_owned);
+        Ok(image)
+    }
+
+    async fn receive_post(&mut self, blog: &str, id: u64, url: String) -> FetchResult {
+        log::info!("Start tumblr process {}/{}", blog, id);
+
+        let envelope: Envelope = self
+            .client
+            .get(format!("{}/v2/blog/{}/posts", self.api_url, blog))
+            .query(&[
+                ("api_key", self.api_key.as_str()),
+                ("id", &id.to_string()),
+                ("npf", "true"),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .on_error(|_| log::error!("Error on request post"))?;
+        let post = envelope
+            .response
+            .posts
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Post {} not found", id))?;
+
+        let images: Vec<_> = join_all(
+            post.images()
+                .into_iter()
+                .map(|(url, alt)| self.download(url, alt)),
+        )
+        .await
+        .into_iter()
+        .filter_map(|r| r.on_error(|e| log::error!("{}", e)).ok())
+        .collect();
+
+        log::info!("Downloaded {} images", images.len());
+
+        let body = ImageRequestBody::from_images(images)
+            .ok_or_else(|| anyhow::anyhow!("Post {} has no images", id))?;
+        let mut request = ImageRequest::new(url, body);
+        request.caption =
+            Some(post.summary.trim().to_owned()).filter(|summary| !summary.is_empty());
+        Ok(request)
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for TumblrReceiveActor {
+    fn name(&self) -> &str {
+        "tumblr"
+    }
+
+    /// Recognizes posts on blog subdomains and on the dashboard.
+    fn link_patterns(&self) -> Vec<LinkPattern> {
+        vec![
+            LinkPattern::new(
+                r"https?://(?P<blog>[\w-]+)\.tumblr\.com/post/(?P<id>\d+)",
+                "https://www.tumblr.com/$blog/$id",
+            ),
+            LinkPattern::new(
+                r"https?://(?:www\.)?tumblr\.com/(?:blog/view/)?(?P<blog>[\w-]+)/(?P<id>\d+)",
+                "https://www.tumblr.com/$blog/$id",
+            ),
+        ]
+    }
+
+    async fn fetch(&mut self, url: String) -> ActorResult<FetchResult> {
+        let post = url
+            .strip_prefix("https://www.tumblr.com/")
+            .and_then(|path| path.split_once('/'))
+            .and_then(|(blog, id)| Some((blog.to_owned(), id.parse().ok()?)));
+
+        Produces::ok(match post {
+            Some((blog, id)) => self.receive_post(&blog, id, url).await,
+            None => Err(anyhow::anyhow!("{} is not a tumblr post", url)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::source::canonical_links;
+
+    #[test]
+    fn recognizes_posts_on_blogs_and_the_dashboard() {
+        let source = TumblrReceiveActor::new(test_config(&[("TUMBLR_API_KEY", "key")]));
+
+        assert_eq!(
+            canonical_links(
+                &source,
+                "https://artist.tumblr.com/post/123/a-title \
+                 https://www.tumblr.com/other-artist/456 \
+                 https://tumblr.com/blog/view/third/789"
+            ),
+            vec![
+                "https://www.tumblr.com/artist/123",
+                "https://www.tumblr.com/other-artist/456",
+                "https://www.tumblr.com/third/789",
+            ]
+        );
+        assert!(canonical_links(&source, "https://artist.tumblr.com/").is_empty());
+    }
+}
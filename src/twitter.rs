@@ -0,0 +1,212 @@
+use crate::config::Config;
+use crate::request::{Image, ImageRequest, ImageRequestBody};
+use crate::source::{FetchResult, LinkPattern, Source};
+use crate::utils::ResultExtension;
+use act_zero::{Actor, ActorResult, Produces};
+use futures::future::join_all;
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// fxtwitter answers with `{"tweet": {...}}`, vxtwitter with the tweet itself.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Response {
+    Fx { tweet: FxTweet },
+    Vx(VxTweet),
+}
+
+#[derive(Deserialize)]
+struct FxTweet {
+    #[serde(default)]
+    text: String,
+    author: FxAuthor,
+    media: Option<FxMedia>,
+}
+
+#[derive(Deserialize)]
+struct FxAuthor {
+    screen_name: String,
+}
+
+#[derive(Deserialize)]
+struct FxMedia {
+    #[serde(default)]
+    all: Vec<Media>,
+}
+
+#[derive(Deserialize)]
+struct VxTweet {
+    #[serde(default)]
+    text: String,
+    user_screen_name: String,
+    #[serde(default)]
+    media_extended: Vec<Media>,
+}
+
+#[derive(Deserialize)]
+struct Media {
+    /// `photo` or `image` for photos, `video` or `gif` for videos.
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+}
+
+struct Tweet {
+    text: String,
+    author: String,
+    media: Vec<Media>,
+}
+
+impl From<Response> for Tweet {
+    fn from(response: Response) -> Self {
+        match response {
+            Response::Fx { tweet } => Tweet {
+                text: tweet.text,
+                author: tweet.author.screen_name,
+                media: tweet.media.map(|media| media.all).unwrap_or_default(),
+            },
+            Response::Vx(tweet) => Tweet {
+                text: tweet.text,
+                author: tweet.user_screen_name,
+                media: tweet.media_extended,
+            },
+        }
+    }
+}
+
+/// Receives tweets through an fxtwitter or vxtwitter compatible api.
+pub struct TwitterReceiveActor {
+    client: reqwest::Client,
+    api_url: String,
+}
+
+impl Actor for TwitterReceiveActor {}
+
+impl TwitterReceiveActor {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_url: config.twitter_api_url.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    async fn download(&self, media: &Media) -> anyhow::Result<Image> {
+        let video = matches!(media.kind.as_str(), "video" | "gif");
+        let url = if video {
+            media.url.clone()
+        } else {
+            let url = media.url.split('?').next().unwrap();
+            format!("{}?name=orig", url)
+        };
+
+        let data = self
+            .client
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+            .on_error(|_| log::error!("Error on download image"))?;
+
+        let path = media.url.split('?').next().unwrap();
+        let filename = path.rsplit('/').next().unwrap().to_string();
+        Ok(if video {
+            Image::video(filename, data.as_ref().into())
+        } else {
+            Image::new(filename, data.as_ref().into())
+        })
+    }
+
+    async fn receive_status(&mut self, user: &str, id: u64, url: String) -> FetchResult {
+        log::info!("Start twitter process {}", id);
+
+        let response: Response = self
+            .client
+            .get(format!("{}/{}/status/{}", self.api_url, user, id))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .on_error(|_| log::error!("Error on request tweet"))?;
+        let tweet = Tweet::from(response);
+
+        let images: Vec<_> = join_all(tweet.media.iter().map(|media| self.download(media)))
+            .await
+            .into_iter()
+            .filter_map(|r| r.on_error(|e| log::error!("{}", e)).ok())
+            .collect();
+
+        log::info!("Downloaded {} images", images.len());
+
+        let body = ImageRequestBody::from_images(images)
+            .ok_or_else(|| anyhow::anyhow!("Tweet {} has no media", id))?;
+        let mut request = ImageRequest::new(url, body);
+        request.caption = Some(
+            Some(tweet.text.trim())
+                .filter(|text| !text.is_empty())
+                .map(|text| format!("{}\n\n@{}", text, tweet.author))
+                .unwrap_or_else(|| format!("@{}", tweet.author)),
+        );
+        Ok(request)
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for TwitterReceiveActor {
+    fn name(&self) -> &str {
+        "twitter"
+    }
+
+    /// Recognizes status links on twitter.com, x.com and the embed fixing mirrors.
+    fn link_patterns(&self) -> Vec<LinkPattern> {
+        vec![LinkPattern::new(
+            r"https?://(?:(?:www|mobile)\.)?(?:twitter|x|fxtwitter|vxtwitter|fixupx|fixvx)\.com/(?P<user>\w+)/status(?:es)?/(?P<id>\d+)",
+            "https://x.com/$user/status/$id",
+        )]
+    }
+
+    async fn fetch(&mut self, url: String) -> ActorResult<FetchResult> {
+        let status = url
+            .strip_prefix("https://x.com/")
+            .and_then(|path| path.split_once("/status/"))
+            .and_then(|(user, id)| Some((user.to_owned(), id.parse().ok()?)));
+
+        Produces::ok(match status {
+            Some((user, id)) => self.receive_status(&user, id, url).await,
+            None => Err(anyhow::anyhow!("{} is not a tweet", url)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::source::canonical_links;
+
+    #[test]
+    fn recognizes_statuses_on_every_mirror() {
+        let source = TwitterReceiveActor::new(test_config(&[]));
+
+        for link in [
+            "https://twitter.com/artist/status/123",
+            "https://mobile.twitter.com/artist/status/123?s=20",
+            "https://x.com/artist/status/123/photo/1",
+            "https://fxtwitter.com/artist/statuses/123",
+            "https://vxtwitter.com/artist/status/123",
+            "https://fixupx.com/artist/status/123",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                canonical_links(&source, link),
+                vec!["https://x.com/artist/status/123"],
+                "{}",
+                link
+            );
+        }
+        assert!(canonical_links(&source, "https://x.com/artist").is_empty());
+    }
+}
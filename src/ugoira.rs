@@ -0,0 +1,165 @@
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame};
+use std::io::{Cursor, Read};
+use std::path::Path;
+use tokio::fs;
+use tokio::process::Command;
+
+/// One still of an ugoira and how long it is shown.
+pub struct UgoiraFrame {
+    pub data: Vec<u8>,
+    pub delay_ms: u32,
+}
+
+/// Takes the frames listed in the metadata out of the archive, in their order.
+pub fn unpack<'a>(
+    archive: &[u8],
+    frames: impl Iterator<Item = (&'a str, u32)>,
+) -> anyhow::Result<Vec<UgoiraFrame>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(archive))?;
+
+    frames
+        .map(|(file, delay_ms)| {
+            let mut data = Vec::new();
+            archive
+                .by_name(file)
+                .map_err(|e| anyhow::anyhow!("Frame {} is missing: {}", file, e))?
+                .read_to_end(&mut data)?;
+            Ok(UgoiraFrame {
+                data,
+                delay_ms,
+            })
+        })
+        .collect()
+}
+
+/// Endlessly looping GIF of the frames. Slow for large works, better run outside the runtime.
+pub fn encode_gif(frames: &[UgoiraFrame]) -> anyhow::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    {
+        let mut encoder = GifEncoder::new_with_speed(&mut buffer, 10);
+        encoder.set_repeat(Repeat::Infinite)?;
+        for frame in frames {
+            let image = image::load_from_memory(&frame.data)?.to_rgba8();
+            let delay = Delay::from_numer_denom_ms(frame.delay_ms, 1);
+            encoder.encode_frame(Frame::from_parts(image, 0, 0, delay))?;
+        }
+    }
+    Ok(buffer)
+}
+
+/// H.264 MP4 of the frames with their own durations, made by `ffmpeg` from the `PATH`.
+pub async fn encode_mp4(frames: &[UgoiraFrame], name: &str) -> anyhow::Result<Vec<u8>> {
+    let directory = std::env::temp_dir().join(format!("ugoira_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&directory).await?;
+
+    let encoded = run_ffmpeg(frames, &directory).await;
+    if let Err(e) = fs::remove_dir_all(&directory).await {
+        log::warn!("Error on remove {}: {}", directory.display(), e);
+    }
+    encoded
+}
+
+/// Name the frame is written under, the names in the metadata are not trusted as paths.
+fn frame_file(index: usize, frame: &UgoiraFrame) -> String {
+    let extension = image::guess_format(&frame.data)
+        .ok()
+        .and_then(|format| format.extensions_str().first().copied())
+        .unwrap_or("jpg");
+    format!("{:06}.{}", index, extension)
+}
+
+async fn run_ffmpeg(frames: &[UgoiraFrame], directory: &Path) -> anyhow::Result<Vec<u8>> {
+    // The concat demuxer ignores the duration of the last file unless it is listed again
+    let mut list = String::from("ffconcat version 1.0\n");
+    let mut last = None;
+    for (index, frame) in frames.iter().enumerate() {
+        let file = frame_file(index, frame);
+        fs::write(directory.join(&file), &frame.data).await?;
+        list.push_str(&format!(
+            "file '{}'\nduration {:.3}\n",
+            file,
+            frame.delay_ms as f64 / 1000.0
+        ));
+        last = Some(file);
+    }
+    if let Some(last) = last {
+        list.push_str(&format!("file '{}'\n", last));
+    }
+    fs::write(directory.join("frames.txt"), list).await?;
+
+    let output = directory.join("ugoira.mp4");
+    let status = Command::new("ffmpeg")
+        .current_dir(directory)
+        .args(["-y", "-loglevel", "error", "-f", "concat", "-safe", "0", "-i", "frames.txt"])
+        // H.264 in yuv420p needs even dimensions
+        .args(["-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2", "-pix_fmt", "yuv420p"])
+        .args(["-c:v", "libx264", "-vsync", "vfr", "-movflags", "+faststart"])
+        .arg(&output)
+        .status()
+        .await
+        .map_err(|e| anyhow::anyhow!("Error on run ffmpeg: {}", e))?;
+    if !status.success() {
+        anyhow::bail!("ffmpeg exited with {}", status);
+    }
+
+    Ok(fs::read(output).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, RgbaImage};
+    use std::io::Write;
+
+    fn png(color: u8) -> Vec<u8> {
+        let mut data = Vec::new();
+        RgbaImage::from_pixel(3, 2, image::Rgba([color, color, color, 255]))
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+        data
+    }
+
+    fn archive(files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer
+                .start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn unpacks_frames_in_metadata_order() {
+        let archive = archive(&[("000000.png", png(0)), ("000001.png", png(255))]);
+        let frames = unpack(&archive, vec![("000001.png", 40), ("000000.png", 60)].into_iter()).unwrap();
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].data, png(255));
+        assert_eq!(frames[0].delay_ms, 40);
+        assert_eq!(frames[1].delay_ms, 60);
+
+        assert!(unpack(&archive, vec![("missing.png", 40)].into_iter()).is_err());
+    }
+
+    #[test]
+    fn encodes_a_looping_gif() {
+        let archive = archive(&[("a.png", png(0)), ("b.png", png(255))]);
+        let frames = unpack(&archive, vec![("a.png", 100), ("b.png", 250)].into_iter()).unwrap();
+        let gif = encode_gif(&frames).unwrap();
+
+        assert!(gif.starts_with(b"GIF89a"));
+        assert_eq!(image::guess_format(&gif).unwrap(), ImageFormat::Gif);
+    }
+
+    #[test]
+    fn writes_frames_under_generated_names() {
+        let frame = |data: Vec<u8>| UgoiraFrame { data, delay_ms: 100 };
+
+        assert_eq!(frame_file(0, &frame(png(0))), "000000.png");
+        assert_eq!(frame_file(12, &frame(vec![0xFF, 0xD8, 0xFF, 0xE0])), "000012.jpg");
+        assert_eq!(frame_file(3, &frame(b"../../'\nfile".to_vec())), "000003.jpg");
+    }
+}
@@ -1,4 +1,8 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::Url;
 use std::fmt::Display;
+use std::net::IpAddr;
 
 pub trait ResultExtension: Sized {
     type Ok;
@@ -20,3 +24,178 @@ impl<T, E: Display> ResultExtension for Result<T, E> {
         self
     }
 }
+
+/// Plain text of an HTML fragment: line breaks and paragraphs become newlines,
+/// other tags are dropped and the common entities are decoded.
+pub fn html_to_text(html: &str) -> String {
+    static BREAKS: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)<br\s*/?>|</p>\s*<p[^>]*>").unwrap());
+    static TAGS: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+    let text = BREAKS.replace_all(html, "\n");
+    let text = TAGS.replace_all(&text, "");
+    decode_entities(&text).trim().to_owned()
+}
+
+/// Decodes the common HTML entities, leaving tags as they are.
+pub fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+/// Fails unless `url` is an http(s) link to a public host, see [`check_public_host`].
+pub async fn check_public_url(url: &str) -> anyhow::Result<()> {
+    let parsed = Url::parse(url).map_err(|e| anyhow::anyhow!("Invalid link {}: {}", url, e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        anyhow::bail!("{} is not an http link", url);
+    }
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| anyhow::anyhow!("{} has no host", url))?;
+    check_public_host(host).await
+}
+
+/// Fails for IP literals and for hosts that resolve to loopback, private or otherwise internal
+/// addresses, so links sent by users cannot make the bot reach the network it runs in.
+pub async fn check_public_host(host: &str) -> anyhow::Result<()> {
+    check_host_name(host)?;
+
+    let host = host.to_lowercase();
+    let addresses = tokio::net::lookup_host((host.as_str(), 443))
+        .await
+        .map_err(|e| anyhow::anyhow!("Error on resolve {}: {}", host, e))?;
+    for address in addresses {
+        if !is_public_ip(address.ip()) {
+            anyhow::bail!("{} resolves to the internal address {}", host, address.ip());
+        }
+    }
+    Ok(())
+}
+
+/// Redirects only to hosts that pass the checks of [`check_public_host`] not needing a lookup.
+pub fn public_redirect_policy() -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(|attempt| {
+        let host = attempt.url().host_str().unwrap_or_default().to_owned();
+        if attempt.previous().len() >= 10 {
+            attempt.error(anyhow::anyhow!("Too many redirects"))
+        } else if let Err(e) = check_host_name(&host) {
+            attempt.error(e)
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+fn check_host_name(host: &str) -> anyhow::Result<()> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.parse::<IpAddr>().is_ok() {
+        anyhow::bail!("{} is an ip address, only domain names are allowed", host);
+    }
+
+    let host = host.to_lowercase();
+    if !host.contains('.') || host.ends_with(".localhost") || host.ends_with(".local") {
+        anyhow::bail!("{} is not a public host", host);
+    }
+    Ok(())
+}
+
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || a == 0
+                // Shared address space of carrier-grade NAT
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    // Unique local and link-local addresses
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_to_text_keeps_line_breaks() {
+        assert_eq!(
+            html_to_text("<p>First <a href=\"https://example.com\">link</a></p><p>Second<br>third<BR/>fourth</p>"),
+            "First link\nSecond\nthird\nfourth"
+        );
+    }
+
+    #[test]
+    fn html_to_text_decodes_entities_once() {
+        assert_eq!(
+            html_to_text("  &lt;b&gt; &quot;a&quot; &#39;b&apos;&nbsp;&amp;lt; "),
+            "<b> \"a\" 'b' &lt;"
+        );
+    }
+
+    #[test]
+    fn decode_entities_keeps_tags() {
+        assert_eq!(
+            decode_entities("<a href=\"/?a=1&amp;b=2\">&lt;b&gt;</a>"),
+            "<a href=\"/?a=1&b=2\"><b></a>"
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_internal_hosts() {
+        for host in [
+            "127.0.0.1",
+            "169.254.169.254",
+            "[::1]",
+            "::1",
+            "localhost",
+            "metadata",
+            "printer.local",
+        ] {
+            assert!(check_public_host(host).await.is_err(), "{}", host);
+        }
+        for url in ["http://10.0.0.1/image.png", "file:///etc/passwd", "https://localhost:8080/"] {
+            assert!(check_public_url(url).await.is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn tells_public_addresses() {
+        for ip in ["1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "0.0.0.0",
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use crate::config::Config;
-use crate::request::{Image, ImageRequest, ImageRequestBody, ImageSender};
+use crate::request::{DeliveryResult, Image, ImageRequest, ImageRequestBody, ImageSender};
 use crate::utils::ResultExtension;
 use act_zero::{Actor, ActorResult, Produces};
 use itertools::Itertools;
@@ -28,9 +28,8 @@ impl Actor for VkSenderActor {
     }
 }
 
-#[async_trait::async_trait]
-impl ImageSender for VkSenderActor {
-    async fn handle_request(&mut self, request: Arc<ImageRequest>) -> ActorResult<()> {
+impl VkSenderActor {
+    async fn send_request(&self, request: &ImageRequest) -> anyhow::Result<()> {
         log::info!("Start handle {} with vk target", request.source);
 
         let mut images = vec![];
@@ -40,29 +39,61 @@ impl ImageSender for VkSenderActor {
         };
 
         let url = get_upload_server(&self.api, self.config.vk_target).await?;
+        let total = images.len();
         let images: Vec<_> =
-            futures::future::join_all(images.into_iter().map(|i| upload_photo(&self.api, &url, i)))
+            futures::future::join_all(images.into_iter().map(|i| self.upload(&url, i)))
                 .await
                 .into_iter()
                 .filter_map(|i| i.on_error(|_| log::error!("Error on photo upload")).ok())
                 .collect();
+        if images.is_empty() {
+            anyhow::bail!("No photo was uploaded");
+        }
 
-        for imgs in images.chunks(10) {
-            let attachment = imgs
-                .iter()
-                .map(|img| format!("photo{}_{}", img[0].owner_id, img[0].id))
-                .join(",");
+        for (i, imgs) in images.chunks(10).enumerate() {
+            let attachment = imgs.iter().join(",");
 
-            let params = maplit::hashmap! {
+            let mut params = maplit::hashmap! {
                 "peer_id".into() => self.config.vk_target.to_string(),
                 "attachment".into() => attachment,
                 "random_id".into() => rand::thread_rng().next_u64().to_string()
             };
+            if i == 0 {
+                params.insert("message".into(), request.text());
+            }
 
             rvk_methods::messages::send::<serde_json::Value>(&self.api, params).await?;
         }
 
-        Produces::ok(())
+        if images.len() < total {
+            anyhow::bail!("Uploaded only {} of {} photos", images.len(), total);
+        }
+
+        Ok(())
+    }
+}
+
+impl VkSenderActor {
+    /// Uploads photos as message photos, videos and animated GIFs as documents,
+    /// returns the attachment.
+    async fn upload(&self, photo_upload_url: &str, image: &Image) -> anyhow::Result<String> {
+        if image.is_video() || image.is_animation() {
+            let doc = upload_doc(&self.api, self.config.vk_target, image).await?;
+            Ok(format!("doc{}_{}", doc.owner_id, doc.id))
+        } else {
+            let photo = upload_photo(&self.api, photo_upload_url, image).await?;
+            let photo = photo
+                .first()
+                .ok_or_else(|| anyhow::anyhow!("No photo saved"))?;
+            Ok(format!("photo{}_{}", photo.owner_id, photo.id))
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ImageSender for VkSenderActor {
+    async fn handle_request(&mut self, request: Arc<ImageRequest>) -> ActorResult<DeliveryResult> {
+        Produces::ok(self.send_request(&request).await)
     }
 }
 
@@ -112,11 +143,45 @@ async fn upload_photo(
     )
 }
 
+async fn upload_doc(api: &rvk::APIClient, peer_id: i64, image: &Image) -> anyhow::Result<Doc> {
+    let server = rvk_methods::docs::get_messages_upload_server::<GetUploadServer>(
+        api,
+        maplit::hashmap! {
+            "type".into() => "doc".to_owned(),
+            "peer_id".into() => peer_id.to_string(),
+        },
+    )
+    .await?;
+
+    let form = reqwest::multipart::Form::new().part(
+        "file",
+        reqwest::multipart::Part::bytes(image.data.as_ref().to_owned())
+            .file_name(image.filename.clone()),
+    );
+    let result: DocUploadResult = reqwest::Client::new()
+        .post(&server.upload_url)
+        .multipart(form)
+        .send()
+        .await
+        .on_error(|_| log::error!("Error on upload doc"))?
+        .json()
+        .await?;
+
+    let saved: SavedDoc = rvk_methods::docs::save(
+        api,
+        maplit::hashmap! {
+            "file".into() => result.file,
+        },
+    )
+    .await
+    .on_error(|_| log::error!("Error on save doc"))?;
+
+    Ok(saved.doc)
+}
+
 #[derive(Deserialize, Debug)]
 struct GetUploadServer {
     upload_url: String,
-    album_id: i64,
-    user_id: i64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -126,6 +191,22 @@ struct UploadResult {
     hash: String,
 }
 
+#[derive(Deserialize, Debug)]
+struct DocUploadResult {
+    file: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SavedDoc {
+    doc: Doc,
+}
+
+#[derive(Deserialize, Debug)]
+struct Doc {
+    id: i64,
+    owner_id: i64,
+}
+
 #[derive(Deserialize, Debug)]
 struct Photo {
     id: i64,
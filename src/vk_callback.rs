@@ -0,0 +1,158 @@
+use crate::auth::constant_time_eq;
+use crate::config::Config;
+use crate::jobs::{ingest_text, JobManagerActor};
+use crate::source::SourceRegistry;
+use crate::utils::ResultExtension;
+use act_zero::Addr;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::{Json, Router};
+use rand::RngCore;
+use serde::Deserialize;
+use std::sync::Arc;
+
+struct CallbackState {
+    confirmation: String,
+    secret: String,
+    allowed_peers: Vec<i64>,
+    api: rvk::APIClient,
+    sources: Arc<SourceRegistry>,
+    jobs: Addr<JobManagerActor>,
+}
+
+/// Route for the VK Callback API, only present when a confirmation string and a secret
+/// are configured.
+///
+/// Links in new messages from allowed peers are queued like `/post` and the result
+/// is sent back as a reply.
+pub fn router(
+    config: &Config,
+    sources: Arc<SourceRegistry>,
+    jobs: Addr<JobManagerActor>,
+) -> Router {
+    let confirmation = match &config.vk_callback_confirmation {
+        Some(confirmation) => confirmation.clone(),
+        None => return Router::new(),
+    };
+    // Without a secret anyone could post forged messages to the route
+    let secret = match &config.vk_callback_secret {
+        Some(secret) if !secret.is_empty() => secret.clone(),
+        _ => {
+            log::error!("VK callback is disabled: vk_callback_secret is not set");
+            return Router::new();
+        }
+    };
+
+    let state = CallbackState {
+        confirmation,
+        secret,
+        allowed_peers: config.vk_allowed_peers.clone(),
+        api: rvk_methods::supported_api_client(config.vk_bot_token.clone()),
+        sources,
+        jobs,
+    };
+
+    Router::new()
+        .route("/vk/callback", post(callback))
+        .with_state(Arc::new(state))
+}
+
+#[derive(Deserialize)]
+struct CallbackEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    secret: Option<String>,
+    #[serde(default)]
+    object: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct MessageNew {
+    message: VkMessage,
+}
+
+#[derive(Deserialize)]
+struct VkMessage {
+    from_id: i64,
+    peer_id: i64,
+    #[serde(default)]
+    conversation_message_id: i64,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+}
+
+#[derive(Deserialize)]
+struct Attachment {
+    link: Option<LinkAttachment>,
+}
+
+#[derive(Deserialize)]
+struct LinkAttachment {
+    url: String,
+}
+
+async fn callback(
+    State(state): State<Arc<CallbackState>>,
+    Json(event): Json<CallbackEvent>,
+) -> Result<String, StatusCode> {
+    // VK sends the secret with every event, confirmation included
+    let secret = event.secret.as_deref().unwrap_or_default();
+    if !constant_time_eq(secret.as_bytes(), state.secret.as_bytes()) {
+        log::warn!("Rejected VK callback {} with invalid secret", event.kind);
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    if event.kind == "confirmation" {
+        return Ok(state.confirmation.clone());
+    }
+
+    if event.kind == "message_new" {
+        let message = serde_json::from_value::<MessageNew>(event.object)
+            .on_error(|e| log::error!("Error on parse VK message: {}", e))
+            .map_err(|_| StatusCode::BAD_REQUEST)?
+            .message;
+
+        let allowed = state.allowed_peers.contains(&message.from_id)
+            || state.allowed_peers.contains(&message.peer_id);
+        if allowed {
+            tokio::spawn(handle_message(state.clone(), message));
+        } else {
+            log::warn!("Ignore VK message from not allowed peer {}", message.from_id);
+        }
+    }
+
+    Ok("ok".to_owned())
+}
+
+async fn handle_message(state: Arc<CallbackState>, message: VkMessage) {
+    let text = message
+        .attachments
+        .iter()
+        .filter_map(|attachment| attachment.link.as_ref())
+        .fold(message.text.clone(), |text, link| text + "\n" + &link.url);
+
+    let reply = |text| reply(&state.api, &message, text);
+    ingest_text(&state.sources, &state.jobs, &text, reply).await;
+}
+
+async fn reply(api: &rvk::APIClient, message: &VkMessage, text: String) {
+    let forward = serde_json::json!({
+        "peer_id": message.peer_id,
+        "conversation_message_ids": [message.conversation_message_id],
+        "is_reply": true,
+    });
+
+    let params = maplit::hashmap! {
+        "peer_id".into() => message.peer_id.to_string(),
+        "message".into() => text,
+        "forward".into() => forward.to_string(),
+        "random_id".into() => rand::thread_rng().next_u64().to_string()
+    };
+
+    let _ = rvk_methods::messages::send::<serde_json::Value>(api, params)
+        .await
+        .on_error(|e| log::error!("Error on reply to VK message: {}", e));
+}